@@ -0,0 +1,194 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Result};
+use reqwest::Url;
+
+use crate::{
+    config::Config,
+    store::{self, IndexEntry, MainMetadata, INDEX_FILE},
+    verify::doc_id,
+    Doc,
+};
+
+/// Characters ending a URL embedded in a document body.
+const URL_END: &[char] = &[
+    '"', '\'', '<', '>', '(', ')', '[', ']', '{', '}', '`', '|', '\\',
+];
+
+/// Reports links in the latest stored copy of each document of the target to hosts outside the
+/// allowlist.
+///
+/// The configured host and its subdomains are always allowed.
+pub fn run(path: &Path, config: &Path) -> Result<()> {
+    let config = Config::load(config)?;
+    let Some(meta_path) = store::find_metadata_path(path, &config.namespace())? else {
+        bail!("no metadata of the target in {}", path.display());
+    };
+    let meta: MainMetadata = serde_json::from_reader(std::fs::File::open(meta_path)?)?;
+    let mut allowlist = config.asset_allowlist.clone();
+    allowlist.extend(Url::parse(&config.host)?.host_str().map(str::to_owned));
+
+    let mut hosts = BTreeSet::new();
+    let mut docs = 0usize;
+    for (id, file) in latest(path, &meta)? {
+        let doc: Doc = serde_json::from_slice(&std::fs::read(&file)?)?;
+        let links: BTreeSet<_> = [&doc.body, &doc.body_html]
+            .into_iter()
+            .flatten()
+            .flat_map(|body| links(body))
+            .filter(|url| {
+                url.host_str()
+                    .is_some_and(|host| !allowlist.iter().any(|allowed| matches(host, allowed)))
+            })
+            .collect();
+        if links.is_empty() {
+            continue;
+        }
+        docs += 1;
+        for url in links {
+            println!("document {id}: {url}");
+            hosts.extend(url.host_str().map(str::to_owned));
+        }
+    }
+
+    eprintln!(
+        "{} documents link to {} hosts outside the allowlist: {}",
+        docs,
+        hosts.len(),
+        hosts.into_iter().collect::<Vec<_>>().join(", ")
+    );
+    Ok(())
+}
+
+/// Finds the latest stored file of each document of the target.
+///
+/// Runs recorded in the metadata of the target are taken in order. Directories no target recorded,
+/// like those of runs before runs were recorded, are taken before them by modification time,
+/// limited to documents tracked for the target.
+fn latest(path: &Path, meta: &MainMetadata) -> Result<BTreeMap<i64, PathBuf>> {
+    let mut recorded = HashSet::new();
+    for meta_path in store::metadata_paths(path)? {
+        let meta: MainMetadata = serde_json::from_reader(std::fs::File::open(meta_path)?)?;
+        recorded.extend(meta.runs.into_iter().filter_map(|run| run.dir));
+    }
+    let mut unrecorded = HashMap::<i64, (SystemTime, PathBuf)>::new();
+    for entry in std::fs::read_dir(path)? {
+        let dir = entry?.path();
+        if !dir.is_dir()
+            || dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| recorded.contains(name))
+        {
+            continue;
+        }
+        for file in doc_files(&dir)? {
+            let Some(id) = doc_id(&file).filter(|id| meta.items.contains_key(id)) else {
+                continue;
+            };
+            let modified = file.metadata()?.modified()?;
+            if unrecorded.get(&id).is_none_or(|(time, _)| *time < modified) {
+                unrecorded.insert(id, (modified, file));
+            }
+        }
+    }
+    let mut latest: BTreeMap<_, _> = unrecorded
+        .into_iter()
+        .map(|(id, (_, file))| (id, file))
+        .collect();
+
+    // Runs are recorded oldest first, so later copies replace earlier ones.
+    for dir in meta
+        .runs
+        .iter()
+        .filter_map(|run| run.dir.as_ref())
+        .map(|dir| path.join(dir))
+    {
+        // Quarantined or removed.
+        if !dir.is_dir() {
+            continue;
+        }
+        let index = dir.join(INDEX_FILE);
+        if index.try_exists()? {
+            let index: BTreeMap<i64, IndexEntry> = serde_json::from_slice(&std::fs::read(index)?)?;
+            latest.extend(
+                index
+                    .into_iter()
+                    .map(|(id, entry)| (id, dir.join(entry.path))),
+            );
+            continue;
+        }
+        // Runs killed before writing their indexes.
+        for file in doc_files(&dir)? {
+            latest.extend(doc_id(&file).map(|id| (id, file)));
+        }
+    }
+    Ok(latest)
+}
+
+/// Lists files of stored documents in the run directory.
+fn doc_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let file = entry?.path();
+        if doc_id(&file).is_some() {
+            files.push(file);
+        }
+    }
+    Ok(files)
+}
+
+/// Extracts absolute HTTP URLs from the text.
+fn links(text: &str) -> impl Iterator<Item = Url> + '_ {
+    text.match_indices("http").filter_map(|(i, _)| {
+        let rest = &text[i..];
+        if !(rest.starts_with("http://") || rest.starts_with("https://")) {
+            return None;
+        }
+        let end = rest
+            .find(|c: char| c.is_whitespace() || URL_END.contains(&c))
+            .unwrap_or(rest.len());
+        Url::parse(&rest[..end]).ok()
+    })
+}
+
+/// Whether the host is the allowed host or a subdomain of it.
+fn matches(host: &str, allowed: &str) -> bool {
+    host == allowed
+        || host
+            .strip_suffix(allowed)
+            .is_some_and(|sub| sub.ends_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_end_at_delimiters() {
+        let links: Vec<_> = links(
+            r#"<img src="https://cdn.example.com/a.png"> [b](http://b.example.org/x?y=1) httpx https://"#,
+        )
+        .map(String::from)
+        .collect();
+        assert_eq!(
+            links,
+            [
+                "https://cdn.example.com/a.png",
+                "http://b.example.org/x?y=1"
+            ]
+        );
+    }
+
+    #[test]
+    fn subdomains_match() {
+        assert!(matches("cdn.nlark.com", "cdn.nlark.com"));
+        assert!(matches("img.cdn.nlark.com", "cdn.nlark.com"));
+        assert!(!matches("evilcdn.nlark.com", "cdn.nlark.com"));
+        assert!(!matches("nlark.com", "cdn.nlark.com"));
+    }
+}
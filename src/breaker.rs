@@ -0,0 +1,91 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Cool-down of an open circuit when not configured.
+pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// Circuit breakers per host, pausing requests to hosts failing consecutively.
+#[derive(Debug)]
+pub struct Breaker {
+    threshold: Option<usize>,
+    cooldown: Duration,
+    /// Consecutive failures and the end of the cool-down of each host.
+    hosts: RefCell<HashMap<String, (usize, Option<Instant>)>>,
+}
+
+impl Breaker {
+    /// Creates breakers opening after the given number of consecutive failures, if any.
+    pub fn new(threshold: Option<usize>, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            hosts: RefCell::default(),
+        }
+    }
+
+    /// Waits until the circuit of the host is closed.
+    pub async fn wait(&self, host: &str) {
+        let until = self.hosts.borrow().get(host).and_then(|&(_, until)| until);
+        if let Some(until) = until.filter(|&until| until > Instant::now()) {
+            tokio::time::sleep_until(tokio::time::Instant::from_std(until)).await;
+        }
+    }
+
+    /// Records a request to the host with whether it succeeded.
+    ///
+    /// Returns whether the circuit of the host has just opened.
+    pub fn record(&self, host: &str, ok: bool) -> bool {
+        let Some(threshold) = self.threshold else {
+            return false;
+        };
+        let mut hosts = self.hosts.borrow_mut();
+        let (failures, until) = hosts.entry(host.to_owned()).or_default();
+        if ok {
+            *failures = 0;
+            return false;
+        }
+        // Failures are kept after a cool-down, so the first failure after it opens the circuit again.
+        *failures += 1;
+        let now = Instant::now();
+        if *failures < threshold || until.is_some_and(|until| until > now) {
+            return false;
+        }
+        *until = Some(now + self.cooldown);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circuits_open_after_consecutive_failures() {
+        let breaker = Breaker::new(Some(2), DEFAULT_COOLDOWN);
+        assert!(!breaker.record("a", false));
+        assert!(!breaker.record("a", true));
+        assert!(!breaker.record("a", false));
+        assert!(breaker.record("a", false));
+        // Already open.
+        assert!(!breaker.record("a", false));
+        // Hosts are apart.
+        assert!(!breaker.record("b", false));
+    }
+
+    #[test]
+    fn circuits_open_again_after_cooling_down() {
+        let breaker = Breaker::new(Some(2), Duration::ZERO);
+        breaker.record("a", false);
+        assert!(breaker.record("a", false));
+        assert!(breaker.record("a", false));
+    }
+
+    #[test]
+    fn circuits_never_open_without_threshold() {
+        let breaker = Breaker::new(None, DEFAULT_COOLDOWN);
+        assert!((0..100).all(|_| !breaker.record("a", false)));
+    }
+}
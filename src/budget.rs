@@ -0,0 +1,89 @@
+use std::cell::Cell;
+
+use anyhow::{bail, Result};
+
+/// Attempts to be made before the error rate is taken into account.
+const MIN_ATTEMPTS: usize = 20;
+
+/// Error budget of a run, exceeding which aborts the run.
+#[derive(Debug, Default)]
+pub struct Budget {
+    max_errors: Option<usize>,
+    max_rate: Option<f64>,
+    attempts: Cell<usize>,
+    errors: Cell<usize>,
+}
+
+impl Budget {
+    pub fn new(max_errors: Option<usize>, max_rate: Option<f64>) -> Self {
+        Self {
+            max_errors,
+            max_rate,
+            ..Default::default()
+        }
+    }
+
+    /// Records an attempt with whether it succeeded.
+    #[inline]
+    pub fn record(&self, ok: bool) {
+        self.attempts.set(self.attempts.get() + 1);
+        if !ok {
+            self.errors.set(self.errors.get() + 1);
+        }
+    }
+
+    /// Whether the error budget is exceeded.
+    pub fn exceeded(&self) -> bool {
+        let (attempts, errors) = (self.attempts.get(), self.errors.get());
+        self.max_errors.is_some_and(|max| errors >= max)
+            || self.max_rate.is_some_and(|rate| {
+                attempts >= MIN_ATTEMPTS && errors as f64 / attempts as f64 >= rate
+            })
+    }
+
+    /// Fails if the error budget is exceeded.
+    pub fn check(&self) -> Result<()> {
+        if self.exceeded() {
+            bail!(
+                "aborted after {} errors in {} attempts",
+                self.errors.get(),
+                self.attempts.get()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_exceed_the_budget() {
+        let budget = Budget::new(Some(2), None);
+        budget.record(false);
+        budget.record(true);
+        assert!(!budget.exceeded());
+        budget.record(false);
+        assert!(budget.exceeded());
+        assert!(budget.check().is_err());
+    }
+
+    #[test]
+    fn error_rates_count_after_enough_attempts() {
+        let budget = Budget::new(None, Some(0.5));
+        for _ in 0..MIN_ATTEMPTS - 1 {
+            budget.record(false);
+        }
+        assert!(!budget.exceeded());
+        budget.record(true);
+        assert!(budget.exceeded());
+    }
+
+    #[test]
+    fn budgets_are_unlimited_by_default() {
+        let budget = Budget::new(None, None);
+        (0..100).for_each(|_| budget.record(false));
+        assert!(budget.check().is_ok());
+    }
+}
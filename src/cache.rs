@@ -0,0 +1,73 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Name of the cache directory under the backup directory, holding a directory per target.
+pub const CACHE_DIR: &str = "cache";
+
+/// On-disk cache of API listings, expiring after a time-to-live.
+#[derive(Debug)]
+pub struct Cache {
+    dir: Option<PathBuf>,
+    ttl: Duration,
+}
+
+impl Cache {
+    /// Creates a cache under the given directory, removing expired entries.
+    ///
+    /// The cache is disabled if the time-to-live is zero.
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        if ttl.is_zero() {
+            return Self { dir: None, ttl };
+        }
+        for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+            if expired(&entry.path(), ttl) {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+        Self {
+            dir: Some(dir),
+            ttl,
+        }
+    }
+
+    /// Gets the cached response of the given key, if not expired.
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.path(key)?;
+        if expired(&path, self.ttl) {
+            return None;
+        }
+        std::fs::read(path).ok()
+    }
+
+    /// Caches the response of the given key.
+    pub fn put(&self, key: &str, bytes: &[u8]) -> std::io::Result<()> {
+        let (Some(dir), Some(path)) = (&self.dir, self.path(key)) else {
+            return Ok(());
+        };
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(path, bytes)
+    }
+
+    fn path(&self, key: &str) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("listing{}.json", sanitize(key))))
+    }
+}
+
+/// Sanitizes the given key, like an endpoint, into a file name.
+pub fn sanitize(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn expired(path: &Path, ttl: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.elapsed().ok())
+        .is_none_or(|elapsed| elapsed > ttl)
+}
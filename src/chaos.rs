@@ -0,0 +1,73 @@
+use std::{
+    cell::Cell,
+    fmt::Display,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Duration an injected timeout hangs for before failing.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Failure injection into the transport layer, for exercising failure paths.
+#[derive(Debug)]
+pub struct Chaos {
+    rate: f64,
+    /// State of the xorshift generator.
+    state: Cell<u64>,
+}
+
+/// An injected failure.
+#[derive(Debug, Clone, Copy)]
+pub enum Failure {
+    TooManyRequests,
+    ServerError,
+    Timeout,
+}
+
+impl Display for Failure {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::TooManyRequests => write!(f, "429 Too Many Requests"),
+            Failure::ServerError => write!(f, "500 Internal Server Error"),
+            Failure::Timeout => write!(f, "timeout"),
+        }
+    }
+}
+
+impl Chaos {
+    /// Creates a chaos injecting failures into the given ratio of requests.
+    pub fn new(rate: f64) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self {
+            rate,
+            state: Cell::new(seed | 1),
+        }
+    }
+
+    /// Decides whether to fail the next request, and how.
+    pub fn roll(&self) -> Option<Failure> {
+        if self.rate <= 0.0 {
+            return None;
+        }
+        let x = self.next();
+        if (x >> 11) as f64 / (1u64 << 53) as f64 >= self.rate {
+            return None;
+        }
+        Some(match self.next() % 3 {
+            0 => Failure::TooManyRequests,
+            1 => Failure::ServerError,
+            _ => Failure::Timeout,
+        })
+    }
+
+    fn next(&self) -> u64 {
+        let mut x = self.state.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state.set(x);
+        x
+    }
+}
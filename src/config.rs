@@ -1,6 +1,15 @@
-use std::fmt::Display;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    path::Path,
+    str::FromStr,
+};
 
-use serde::Deserialize;
+use anyhow::{bail, Result};
+use serde::{de::Error as _, Deserialize, Deserializer};
+use serde_json::Value;
+use time::UtcOffset;
 
 use crate::Token;
 
@@ -8,12 +17,332 @@ use crate::Token;
 pub struct Config {
     /// The host URL of Yuque organization.
     pub host: String,
+    /// Path prefix the Yuque instance is served under, like `/yuque`.
+    #[serde(default)]
+    pub base_path: String,
     /// Token of your account, or group.
     pub token: Token,
     /// The target user/group to backup.
     pub target: Target,
+    /// HTTP version to request the API with.
+    #[serde(default)]
+    pub protocol: Protocol,
+    /// IP addresses hosts are resolved to, overriding DNS.
+    #[serde(default)]
+    pub resolve: HashMap<String, IpAddr>,
+    /// IP version to connect with.
+    #[serde(default)]
+    pub ip_family: IpFamily,
+    /// Local IP address connections are made from, taking precedence over `ip_family`.
+    #[serde(default)]
+    pub local_address: Option<IpAddr>,
+    /// Network interface connections are bound to, like `eth1`.
+    #[serde(default)]
+    pub interface: Option<String>,
     /// Request limitation per second.
+    ///
+    /// In adaptive mode, this is the upper bound of the tuned limitation.
     pub limit: usize,
+    /// Whether to tune request rate and concurrency automatically.
+    #[serde(default)]
+    pub adaptive: bool,
+    /// Number of errors after which the run aborts.
+    #[serde(default)]
+    pub abort_after_errors: Option<usize>,
+    /// Ratio of failed attempts to list repositories or back up documents, between 0 and 1, at
+    /// which the run aborts.
+    #[serde(default)]
+    pub abort_error_rate: Option<f64>,
+    /// Requests per day, across runs, after which runs stop downloading documents.
+    #[serde(default)]
+    pub max_requests_per_day: Option<u64>,
+    /// Bytes downloaded per day, across runs, after which runs stop downloading documents.
+    #[serde(default)]
+    pub max_bytes_per_day: Option<u64>,
+    /// Time after which the run is cancelled, like `2h`.
+    #[serde(default)]
+    pub max_run_time: Option<Age>,
+    /// Seconds repository and document listings are cached for. Zero disables the cache.
+    #[serde(default)]
+    pub cache_ttl: u64,
+    /// Number of consecutive failed requests to a host after which requests to it pause.
+    #[serde(default)]
+    pub breaker_failures: Option<usize>,
+    /// How long requests to a host pause for once failing, 30 seconds by default.
+    #[serde(default)]
+    pub breaker_cooldown: Option<Age>,
+    /// Time zone run directories are named in, like `local` or `+08:00`.
+    ///
+    /// Timestamps in the metadata are always in UTC.
+    #[serde(default)]
+    pub timezone: TimeZone,
+    /// How run directories are named.
+    #[serde(default)]
+    pub run_naming: RunNaming,
+    /// Order documents are downloaded in.
+    #[serde(default)]
+    pub strategy: Strategy,
+    /// Whether to skip draft documents.
+    ///
+    /// Deprecated alias of `statuses: ["published"]`, ignored when `statuses` is set.
+    #[serde(default)]
+    pub skip_drafts: bool,
+    /// Statuses of documents to back up, all if unset.
+    #[serde(default)]
+    pub statuses: Option<Vec<DocStatus>>,
+    /// Whether to skip listing documents of repositories not updated since fully backed up.
+    #[serde(default)]
+    pub skip_unchanged_repos: bool,
+    /// Whether to capture the table of contents of each repository on each run.
+    #[serde(default)]
+    pub toc: bool,
+    /// Whether to capture statistics of the target group on each run.
+    #[serde(default)]
+    pub statistics: bool,
+    /// Options for debugging.
+    #[serde(default)]
+    pub debug: DebugConfig,
+    /// Whether to capture members of the target group and their roles on each run.
+    #[serde(default)]
+    pub members: bool,
+    /// Size in MiB above which document bodies are warned about.
+    #[serde(default)]
+    pub max_doc_body_mb: Option<u64>,
+    /// Whether to skip documents with bodies larger than `max_doc_body_mb`.
+    #[serde(default)]
+    pub skip_large_docs: bool,
+    /// Whether to add documents the token is forbidden to access to the skip list.
+    #[serde(default)]
+    pub skip_forbidden: bool,
+    /// Whether to store documents as compact JSON instead of pretty-printed JSON.
+    #[serde(default)]
+    pub compact: bool,
+    /// Age of the latest successful run, like `48h`, above which `status` fails.
+    #[serde(default)]
+    pub max_staleness: Option<Age>,
+    /// Hosts, with their subdomains, links to which `audit` doesn't report, like `cdn.nlark.com`.
+    #[serde(default)]
+    pub asset_allowlist: Vec<String>,
+    /// URL a summary of each run is posted to as JSON, for tracking many machines in one place.
+    #[serde(default)]
+    pub report_url: Option<String>,
+    /// Bearer token the summary is posted with.
+    #[serde(default)]
+    pub report_token: Option<Token>,
+    /// Extra authentication for instances behind gateways.
+    #[serde(default)]
+    pub auth: AuthConfig,
+}
+
+/// Maximum depth of configuration files extending each other.
+const MAX_EXTENDS: usize = 8;
+
+impl Config {
+    /// Reads the configuration file, over the file named by its `extends` if any.
+    ///
+    /// The path `-` stands for stdin.
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::load_with(Some(path), &[])
+    }
+
+    /// Reads the configuration file if any, with settings like `target.login=subit` over it.
+    ///
+    /// Values are parsed as JSON, or taken as strings if they aren't valid JSON.
+    pub fn load_with(path: Option<&Path>, settings: &[String]) -> Result<Self> {
+        let mut value = match path {
+            Some(path) => read(path, 0)?,
+            None => Value::Object(Default::default()),
+        };
+        for setting in settings {
+            let Some((key, v)) = setting.split_once('=') else {
+                bail!("setting `{setting}` should be like `key=value`");
+            };
+            let v = serde_json::from_str(v).unwrap_or_else(|_| Value::String(v.to_owned()));
+            let over = key.rsplit('.').fold(v, |v, key| {
+                Value::Object([(key.to_owned(), v)].into_iter().collect())
+            });
+            merge(&mut value, over);
+        }
+        let mut config: Self = serde_json::from_value(value)?;
+        // Joined right after the host, so `yuque` must become `/yuque`.
+        let base_path = config.base_path.trim_matches('/');
+        config.base_path = if base_path.is_empty() {
+            String::new()
+        } else {
+            format!("/{base_path}")
+        };
+        if config.skip_drafts && config.statuses.is_none() {
+            config.statuses = Some(vec![DocStatus::Published]);
+        }
+        if let Some(rate) = config.abort_error_rate {
+            if !(0.0..=1.0).contains(&rate) {
+                bail!("`abort_error_rate` should be between 0 and 1, not {rate}");
+            }
+        }
+        Ok(config)
+    }
+
+    /// Namespace of the metadata of the target, unique across hosts and targets.
+    pub fn namespace(&self) -> String {
+        crate::cache::sanitize(&format!(
+            "{}{}/{}/{}",
+            self.host.trim_end_matches('/'),
+            self.base_path.trim_end_matches('/'),
+            self.target.ty,
+            self.target.login
+        ))
+    }
+
+    /// Whether documents of the publish status are backed up.
+    ///
+    /// Documents without a status are always backed up.
+    pub fn includes_status(&self, status: Option<i32>) -> bool {
+        let Some(status) = status else {
+            return true;
+        };
+        self.statuses
+            .as_ref()
+            .is_none_or(|statuses| statuses.iter().any(|s| s.code() == status))
+    }
+}
+
+fn read(path: &Path, depth: usize) -> Result<Value> {
+    let mut value: Value = if path == Path::new("-") {
+        serde_json::from_reader(std::io::stdin().lock())?
+    } else {
+        serde_json::from_reader(std::fs::File::open(path)?)?
+    };
+    let Some(base) = value.as_object_mut().and_then(|obj| obj.remove("extends")) else {
+        return Ok(value);
+    };
+    let Value::String(base) = base else {
+        bail!("{}: `extends` should be a path", path.display());
+    };
+    if depth >= MAX_EXTENDS {
+        bail!("{}: too many configuration files extended", path.display());
+    }
+    // Relative to the extending file, like includes.
+    let mut base = read(
+        &path.parent().unwrap_or(Path::new("")).join(base),
+        depth + 1,
+    )?;
+    merge(&mut base, value);
+    Ok(base)
+}
+
+/// Merges objects recursively, with values of `over` replacing others.
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// HTTP version to request the API with.
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+pub enum Protocol {
+    /// Negotiated with the server, preferring HTTP/2.
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "http1")]
+    Http1,
+    /// HTTP/2 without negotiation.
+    #[serde(rename = "http2")]
+    Http2,
+}
+
+/// IP version to connect with.
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+pub enum IpFamily {
+    /// Whichever resolved address connects first.
+    #[default]
+    #[serde(rename = "any")]
+    Any,
+    #[serde(rename = "ipv4")]
+    V4,
+    #[serde(rename = "ipv6")]
+    V6,
+}
+
+impl IpFamily {
+    /// Gets the unspecified local address restricting connections to the IP version.
+    pub fn local_address(self) -> Option<IpAddr> {
+        match self {
+            IpFamily::Any => None,
+            IpFamily::V4 => Some(Ipv4Addr::UNSPECIFIED.into()),
+            IpFamily::V6 => Some(Ipv6Addr::UNSPECIFIED.into()),
+        }
+    }
+}
+
+/// How run directories are named.
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+pub enum RunNaming {
+    /// After the time the run started, in the configured time zone.
+    #[default]
+    #[serde(rename = "timestamp")]
+    Timestamp,
+    /// Numbered like `run-000123`, one after the highest existing number.
+    #[serde(rename = "sequence")]
+    Sequence,
+}
+
+/// Publish status of a document.
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub enum DocStatus {
+    #[serde(rename = "draft")]
+    Draft,
+    #[serde(rename = "published")]
+    Published,
+}
+
+impl DocStatus {
+    /// Gets the status code of the API.
+    pub fn code(self) -> i32 {
+        match self {
+            DocStatus::Draft => 0,
+            DocStatus::Published => 1,
+        }
+    }
+}
+
+/// Order documents are downloaded in.
+#[derive(Debug, Deserialize, Default, Clone, Copy)]
+pub enum Strategy {
+    #[serde(rename = "newest_first")]
+    NewestFirst,
+    #[serde(rename = "oldest_first")]
+    OldestFirst,
+    #[serde(rename = "smallest_first")]
+    SmallestFirst,
+    #[default]
+    #[serde(rename = "repo_order")]
+    RepoOrder,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct DebugConfig {
+    /// Whether to dump raw responses into the `raw` directory of each run.
+    #[serde(default)]
+    pub dump_responses: bool,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct AuthConfig {
+    /// Headers sent with every request, like signed headers a gateway requires.
+    #[serde(default)]
+    pub headers: HashMap<String, Token>,
+    /// Session cookie sent with every request.
+    #[serde(default)]
+    pub cookie: Option<Token>,
+    /// Shell command run before the backup, printing further headers as `Name: value` lines.
+    #[serde(default)]
+    pub login_command: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -21,6 +350,9 @@ pub struct Target {
     #[serde(rename = "type")]
     pub ty: TargetType,
     pub login: String,
+    /// Whether to include repositories of groups the target user joined.
+    #[serde(default)]
+    pub include_groups: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -40,3 +372,268 @@ impl Display for TargetType {
         }
     }
 }
+
+/// A time zone, either UTC, the system local one, or a fixed offset.
+#[derive(Debug, Default, Clone, Copy)]
+pub enum TimeZone {
+    #[default]
+    Utc,
+    Local,
+    Offset(UtcOffset),
+}
+
+impl TimeZone {
+    /// Gets the current offset from UTC of the time zone.
+    pub fn offset(self) -> Result<UtcOffset, time::error::IndeterminateOffset> {
+        match self {
+            TimeZone::Utc => Ok(UtcOffset::UTC),
+            TimeZone::Local => UtcOffset::current_local_offset(),
+            TimeZone::Offset(offset) => Ok(offset),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeZone {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "utc" | "UTC" => Ok(TimeZone::Utc),
+            "local" => Ok(TimeZone::Local),
+            _ => UtcOffset::parse(
+                &s,
+                time::macros::format_description!("[offset_hour sign:mandatory]:[offset_minute]"),
+            )
+            .map(TimeZone::Offset)
+            .map_err(D::Error::custom),
+        }
+    }
+}
+
+/// A span of time written as a number with a unit, like `30m`, `48h` or `7d`.
+#[derive(Debug, Clone, Copy)]
+pub struct Age(pub time::Duration);
+
+impl FromStr for Age {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (n, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let n: i64 = n.parse().map_err(|err| format!("{err}"))?;
+        let unit = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => {
+                return Err(format!(
+                    "unknown unit of `{s}`, expected `s`, `m`, `h` or `d`"
+                ))
+            }
+        };
+        n.checked_mul(unit)
+            .map(|secs| Age(time::Duration::seconds(secs)))
+            .ok_or_else(|| format!("`{s}` is too long"))
+    }
+}
+
+impl<'de> Deserialize<'de> for Age {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Settings of the required fields.
+    const REQUIRED: [&str; 5] = [
+        "host=https://www.yuque.com",
+        "token=secret",
+        "target.type=users",
+        "target.login=subit",
+        "limit=5",
+    ];
+
+    /// Creates an empty directory for the test under the temporary directory.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("yuque-squirrel-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn merge_replaces_nested_values() {
+        let mut base = json!({"target": {"type": "users", "login": "a"}, "limit": 1});
+        merge(&mut base, json!({"target": {"login": "b"}, "toc": true}));
+        assert_eq!(
+            base,
+            json!({"target": {"type": "users", "login": "b"}, "limit": 1, "toc": true})
+        );
+    }
+
+    #[test]
+    fn settings_set_nested_fields() {
+        let settings: Vec<_> = REQUIRED
+            .iter()
+            .map(|s| s.to_string())
+            .chain(["statuses=[\"published\"]".to_owned()])
+            .collect();
+        let config = Config::load_with(None, &settings).unwrap();
+        assert_eq!(config.host, "https://www.yuque.com");
+        assert_eq!(config.target.login, "subit");
+        assert_eq!(config.limit, 5);
+        assert!(config.includes_status(Some(DocStatus::Published.code())));
+        assert!(!config.includes_status(Some(DocStatus::Draft.code())));
+    }
+
+    #[test]
+    fn skip_drafts_is_an_alias_of_statuses() {
+        let load = |extra: &[&str]| {
+            let settings: Vec<_> = REQUIRED
+                .iter()
+                .chain(extra)
+                .map(|s| s.to_string())
+                .collect();
+            Config::load_with(None, &settings).unwrap()
+        };
+        let draft = Some(DocStatus::Draft.code());
+        let published = Some(DocStatus::Published.code());
+
+        let config = load(&["skip_drafts=true"]);
+        assert!(!config.includes_status(draft));
+        assert!(config.includes_status(published));
+
+        let config = load(&["skip_drafts=true", "statuses=[\"draft\"]"]);
+        assert!(config.includes_status(draft));
+        assert!(!config.includes_status(published));
+    }
+
+    #[test]
+    fn base_paths_start_with_slashes() {
+        for (base_path, normalized) in [
+            ("yuque", "/yuque"),
+            ("/yuque/", "/yuque"),
+            ("/a/b", "/a/b"),
+            ("/", ""),
+            ("", ""),
+        ] {
+            let settings: Vec<_> = REQUIRED
+                .iter()
+                .map(|s| s.to_string())
+                .chain([format!("base_path={base_path}")])
+                .collect();
+            let config = Config::load_with(None, &settings).unwrap();
+            assert_eq!(config.base_path, normalized);
+        }
+    }
+
+    #[test]
+    fn later_settings_override_earlier_ones() {
+        let settings: Vec<_> = REQUIRED
+            .iter()
+            .map(|s| s.to_string())
+            .chain(["limit=7".to_owned()])
+            .collect();
+        assert_eq!(Config::load_with(None, &settings).unwrap().limit, 7);
+    }
+
+    #[test]
+    fn error_rates_out_of_range_are_rejected() {
+        for rate in ["0.2", "1"] {
+            let settings: Vec<_> = REQUIRED
+                .iter()
+                .map(|s| s.to_string())
+                .chain([format!("abort_error_rate={rate}")])
+                .collect();
+            assert!(Config::load_with(None, &settings).is_ok());
+        }
+        for rate in ["-0.1", "20"] {
+            let settings: Vec<_> = REQUIRED
+                .iter()
+                .map(|s| s.to_string())
+                .chain([format!("abort_error_rate={rate}")])
+                .collect();
+            assert!(Config::load_with(None, &settings).is_err());
+        }
+    }
+
+    #[test]
+    fn settings_without_values_are_rejected() {
+        assert!(Config::load_with(None, &["host".to_owned()]).is_err());
+    }
+
+    #[test]
+    fn extended_files_are_overridden() {
+        let dir = temp_dir("extends");
+        std::fs::write(
+            dir.join("base.json"),
+            json!({
+                "host": "https://www.yuque.com",
+                "token": "secret",
+                "target": {"type": "groups", "login": "subit"},
+                "limit": 1,
+            })
+            .to_string(),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("config.json"),
+            json!({"extends": "base.json", "limit": 3}).to_string(),
+        )
+        .unwrap();
+        let config = Config::load(&dir.join("config.json")).unwrap();
+        assert_eq!(config.limit, 3);
+        assert_eq!(config.target.login, "subit");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn cyclic_extends_are_rejected() {
+        let dir = temp_dir("cycle");
+        std::fs::write(
+            dir.join("config.json"),
+            json!({"extends": "config.json"}).to_string(),
+        )
+        .unwrap();
+        assert!(Config::load(&dir.join("config.json")).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ages_are_parsed() {
+        assert_eq!("30s".parse::<Age>().unwrap().0, time::Duration::seconds(30));
+        assert_eq!("30m".parse::<Age>().unwrap().0, time::Duration::minutes(30));
+        assert_eq!("48h".parse::<Age>().unwrap().0, time::Duration::hours(48));
+        assert_eq!("7d".parse::<Age>().unwrap().0, time::Duration::days(7));
+        assert!("7".parse::<Age>().is_err());
+        assert!("d".parse::<Age>().is_err());
+        assert!("7w".parse::<Age>().is_err());
+        assert!("9999999999999999d".parse::<Age>().is_err());
+    }
+
+    #[test]
+    fn time_zones_are_parsed() {
+        let zone = |s: &str| serde_json::from_value::<TimeZone>(json!(s));
+        assert!(matches!(zone("utc").unwrap(), TimeZone::Utc));
+        assert!(matches!(zone("local").unwrap(), TimeZone::Local));
+        assert_eq!(
+            zone("+08:00").unwrap().offset().unwrap(),
+            UtcOffset::from_hms(8, 0, 0).unwrap()
+        );
+        assert_eq!(
+            zone("-03:30").unwrap().offset().unwrap(),
+            UtcOffset::from_hms(-3, -30, 0).unwrap()
+        );
+        assert!(zone("08:00").is_err());
+        assert!(zone("Asia/Shanghai").is_err());
+    }
+}
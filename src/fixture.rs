@@ -0,0 +1,72 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::cache;
+
+/// Namespace of the metadata of runs against fixtures, kept apart from real targets.
+pub const NAMESPACE: &str = "fixture";
+
+/// Serves canned API responses from the given directory on a local port, in the background.
+///
+/// Responses are files named like dumped raw responses, so the `raw` directory of a run can be
+/// served as is. Requests without a matching file are answered with 404.
+pub fn serve(dir: PathBuf) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))?;
+    let addr = listener.local_addr()?;
+    let dir = Arc::new(dir);
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let dir = Arc::clone(&dir);
+            std::thread::spawn(move || {
+                match respond(&dir, stream) {
+                    // Clients hang up on cancelled requests.
+                    Err(err)
+                        if !matches!(
+                            err.kind(),
+                            io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+                        ) =>
+                    {
+                        eprintln!("fixture server: {err}")
+                    }
+                    _ => {}
+                }
+            });
+        }
+    });
+    Ok(addr)
+}
+
+fn respond(dir: &Path, mut stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let target = line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_owned();
+    // Headers are read to the end, as clients may not expect the connection to close early.
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? <= 2 {
+            break;
+        }
+    }
+
+    let file = dir.join(format!("{}.json", cache::sanitize(&target)));
+    let (status, body) = match std::fs::read(&file) {
+        Ok(body) => ("200 OK", body),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => ("404 Not Found", vec![]),
+        Err(err) => return Err(err),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
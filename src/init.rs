@@ -0,0 +1,61 @@
+use std::{
+    io::{ErrorKind, Write},
+    path::Path,
+    process::Command,
+};
+
+use anyhow::{bail, Result};
+
+use crate::store::{MainMetadata, METADATA_FILE};
+
+const CONFIG_FILE: &str = "config.json";
+const CONFIG_TEMPLATE: &str = include_str!("../config-template.json");
+
+/// Initializes a backup directory with a starter configuration and an empty metadata.
+pub fn run(path: &Path, git: bool) -> Result<()> {
+    std::fs::create_dir_all(path)?;
+
+    let config_path = path.join(CONFIG_FILE);
+    write_new(&config_path, CONFIG_TEMPLATE.as_bytes())?;
+    write_new(
+        &path.join(METADATA_FILE),
+        &serde_json::to_vec_pretty(&MainMetadata::default())?,
+    )?;
+
+    if git {
+        // The configuration contains the token, which should never be committed.
+        write_new(
+            &path.join(".gitignore"),
+            format!("{CONFIG_FILE}\n").as_bytes(),
+        )?;
+        if !Command::new("git")
+            .arg("init")
+            .arg(path)
+            .status()?
+            .success()
+        {
+            bail!("failed to initialize git repository");
+        }
+    }
+
+    println!("initialized backup directory at {}", path.display());
+    println!(
+        "fill in `host`, `token` and `target` of {}, then run `yuque-squirrel -c {} {}`",
+        config_path.display(),
+        config_path.display(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Writes a new file, leaving existing files untouched.
+fn write_new(path: &Path, contents: &[u8]) -> Result<()> {
+    match std::fs::File::create_new(path) {
+        Ok(mut file) => file.write_all(contents).map_err(Into::into),
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+            eprintln!("skipping existing {}", path.display());
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
@@ -1,41 +1,94 @@
 use std::{
-    cell::{Cell, RefCell},
+    cell::{Cell, OnceCell, RefCell},
+    collections::{BTreeMap, HashSet},
     fmt::{Debug, Display},
-    path::PathBuf,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::pin,
     rc::Rc,
     time::Instant,
 };
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
+mod audit;
+mod breaker;
+mod budget;
+mod cache;
+mod chaos;
 mod config;
+mod fixture;
+mod init;
 mod net;
+mod quota;
+mod report;
+mod skip;
+mod space;
+mod status;
 mod store;
+mod tune;
+mod usage;
+mod verify;
 
-use config::Config;
+use config::{Age, Config, Protocol, RunNaming, Strategy, TargetType};
+use futures::{stream::FuturesUnordered, StreamExt};
 use time::OffsetDateTime;
 use tokio::io::AsyncWriteExt;
+use tokio_util::sync::CancellationToken;
 
-use crate::store::MainMetadata;
+use crate::{
+    breaker::Breaker,
+    budget::Budget,
+    cache::{Cache, CACHE_DIR},
+    chaos::Chaos,
+    net::{Cancelled, TooLarge},
+    quota::Quota,
+    report::{Change, Event, Reporter, RunSummary, MAX_CHANGES},
+    store::{
+        BackupTime, IndexEntry, MainMetadata, RunItem, Suspicion, INCOMPLETE_MARKER, INDEX_FILE,
+    },
+    tune::Tuner,
+    usage::Usage,
+};
 
 /// The global context.
 #[derive(Debug, Clone, Copy)]
 struct Context<'a> {
     config: &'a Config,
-    h2_client: &'a reqwest::Client,
+    client: &'a reqwest::Client,
 
     limit: &'a Cell<(usize, Instant)>,
+    tuner: &'a Tuner,
     meta: &'a RefCell<MainMetadata>,
+    report: &'a Reporter,
+    cache: &'a Cache,
+    budget: &'a Budget,
+    breaker: &'a Breaker,
+    chaos: &'a Chaos,
+    usage: &'a Usage,
+    quota: &'a Quota,
+    /// Cancellation of the run, checked at each await point of requests.
+    cancel: &'a CancellationToken,
+    /// Directory raw responses are dumped into, if configured.
+    raw_dir: Option<&'a Path>,
+    /// Whether to back up documents which haven't been updated.
+    force: bool,
 }
 
 impl Context<'_> {
-    /// Constructs a [`Url`] with the given suffix.
+    /// Constructs a [`Url`] with the given suffix, under the configured base path.
     #[inline]
     fn url<T: AsRef<str>>(&self, suffix: T) -> Result<Url> {
-        Url::parse(&format!("{}{}", self.config.host, suffix.as_ref())).map_err(Into::into)
+        Url::parse(&format!(
+            "{}{}{}",
+            self.config.host.trim_end_matches('/'),
+            self.config.base_path.trim_end_matches('/'),
+            suffix.as_ref()
+        ))
+        .map_err(Into::into)
     }
 
     #[inline]
@@ -68,11 +121,30 @@ pub struct Repo {
     name: String,
     #[serde(with = "time::serde::iso8601")]
     updated_at: OffsetDateTime,
+    /// Number of documents in the repository.
+    #[serde(default)]
+    items_count: Option<u64>,
+}
+
+/// A group the target user joined, compatible with the API.
+#[derive(Debug, Deserialize)]
+struct Group {
+    login: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct RawDocMeta {
     id: i64,
+    #[serde(default)]
+    slug: String,
+    #[serde(default)]
+    word_count: u64,
+    /// Publish status, `0` for drafts and `1` for published documents.
+    #[serde(default)]
+    status: Option<i32>,
+    /// Visibility, `0` for private, `1` for public and `2` for organization-public documents.
+    #[serde(default)]
+    public: Option<i32>,
     #[serde(with = "time::serde::iso8601")]
     updated_at: OffsetDateTime,
 }
@@ -111,6 +183,22 @@ pub struct Doc {
 #[serde(transparent)]
 pub struct Token(String);
 
+impl Token {
+    /// Formats the token as a bearer credential of the `Authorization` header.
+    pub fn bearer(&self) -> String {
+        format!("Bearer {}", self.0)
+    }
+
+    /// Redacts occurrences of the token in the given text.
+    pub fn redact(&self, text: &str) -> String {
+        if self.0.is_empty() {
+            text.to_owned()
+        } else {
+            text.replace(&self.0, "*****")
+        }
+    }
+}
+
 impl Debug for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "*****")
@@ -129,87 +217,739 @@ impl TryFrom<&Token> for reqwest::header::HeaderValue {
 fn main() -> Result<()> {
     /// Yuque backup utilities.
     #[derive(Parser)]
-    #[command(version, about, long_about = None)]
+    #[command(
+        version,
+        about,
+        long_about = None,
+        args_conflicts_with_subcommands = true,
+        subcommand_negates_reqs = true
+    )]
     struct Cli {
-        /// Path the backup directory is.
-        path: Option<PathBuf>,
+        #[command(subcommand)]
+        command: Option<Command>,
 
-        /// Configuration file.
-        #[arg(short, value_name = "FILE")]
-        config: PathBuf,
+        #[command(flatten)]
+        backup: BackupArgs,
     }
 
-    let Cli { path, config } = Cli::parse();
-    let path = path.unwrap_or_else(|| PathBuf::from(r"./"));
-    let meta_path = path.join("metadata.json");
-    let t_now = OffsetDateTime::now_utc();
-    let backup_path =
-        path.join(t_now.format(&time::format_description::well_known::Iso8601::DATE_TIME)?);
+    #[derive(Subcommand)]
+    enum Command {
+        /// Initializes a backup directory with a starter configuration.
+        Init {
+            /// Path the backup directory is.
+            path: Option<PathBuf>,
+
+            /// Sets up a git repository, ignoring the configuration file.
+            #[arg(long)]
+            git: bool,
+        },
+        /// Verifies stored documents of the backup directory.
+        Verify {
+            /// Path the backup directory is.
+            path: Option<PathBuf>,
+
+            /// Validates documents against the document schema.
+            #[arg(long)]
+            deep: bool,
+        },
+        /// Summarizes the backup directory, failing if backups are stale.
+        Status {
+            /// Path the backup directory is.
+            path: Option<PathBuf>,
+
+            /// Configuration file, for `max_staleness` and the time zone.
+            #[arg(short, value_name = "FILE")]
+            config: Option<PathBuf>,
+
+            /// Lists documents failing to back up, skipped or on the skip list, with reasons.
+            #[arg(long)]
+            failures: bool,
+        },
+        /// Lists or edits documents never backed up.
+        Skip {
+            #[command(subcommand)]
+            action: skip::Action,
+        },
+        /// Lists links of stored documents to hosts outside the configured allowlist.
+        Audit {
+            /// Path the backup directory is.
+            path: Option<PathBuf>,
+
+            /// Configuration file, for `asset_allowlist`.
+            #[arg(short, value_name = "FILE")]
+            config: PathBuf,
+        },
+    }
+
+    let Cli {
+        command,
+        backup: args,
+    } = Cli::parse();
+    match command {
+        Some(Command::Init { path, git }) => init::run(&path.unwrap_or_else(default_path), git),
+        Some(Command::Verify { path, deep }) => {
+            verify::run(&path.unwrap_or_else(default_path), deep)
+        }
+        Some(Command::Status {
+            path,
+            config,
+            failures,
+        }) => status::run(
+            &path.unwrap_or_else(default_path),
+            config.as_deref(),
+            failures,
+        ),
+        Some(Command::Skip { action }) => skip::run(action),
+        Some(Command::Audit { path, config }) => {
+            audit::run(&path.unwrap_or_else(default_path), &config)
+        }
+        None => {
+            // Loaded once, as stdin can only be read once and fixture servers run in the background.
+            let config = backup_config(&args)?;
+            match args.watch {
+                Some(interval) => {
+                    if !interval.0.is_positive() {
+                        anyhow::bail!("the watch interval must be positive");
+                    }
+                    loop {
+                        // A failing run is left for the next one, as in scheduled backups.
+                        if let Err(err) = backup(&args, &config) {
+                            eprintln!("Error: {err:#}");
+                        }
+                        std::thread::sleep(interval.0.unsigned_abs());
+                    }
+                }
+                None => backup(&args, &config),
+            }
+        }
+    }
+}
+
+/// Directory incomplete runs are moved into, under the backup directory.
+const QUARANTINE_DIR: &str = "quarantine";
+
+#[inline]
+fn default_path() -> PathBuf {
+    PathBuf::from(r"./")
+}
+
+/// Arguments of the backup process.
+#[derive(Args)]
+struct BackupArgs {
+    /// Path the backup directory is.
+    path: Option<PathBuf>,
+
+    /// Configuration file, or `-` for stdin.
+    #[arg(short, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Sets a configuration field over the file, like `target.login=subit`.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    settings: Vec<String>,
+
+    /// Emits progress events as JSON lines on stdout.
+    #[arg(long)]
+    progress_events: bool,
+
+    /// Injects random failures into the given ratio of requests.
+    #[arg(long, value_name = "RATE", hide = true)]
+    chaos: Option<f64>,
+
+    /// Backs up documents which haven't been updated, unless identical to their latest backups.
+    #[arg(long)]
+    force: bool,
+
+    /// Moves run directories left incomplete by earlier runs into the `quarantine` directory.
+    #[arg(long)]
+    quarantine_incomplete: bool,
 
-    if !backup_path.try_exists()? {
-        std::fs::create_dir_all(&backup_path)?;
+    /// Backs up from canned responses in the given directory, instead of the configured host.
+    #[arg(long, value_name = "DIR")]
+    fixture_server: Option<PathBuf>,
+
+    /// Keeps backing up at the given interval, like `10m`, listing documents as they change.
+    #[arg(long, value_name = "INTERVAL")]
+    watch: Option<Age>,
+}
+
+/// Loads the configuration of backups, pointed at the fixture server if any.
+fn backup_config(args: &BackupArgs) -> Result<Config> {
+    if args.config.is_none() && args.settings.is_empty() {
+        anyhow::bail!("a configuration is required, with -c or --set");
+    }
+    let mut config = Config::load_with(args.config.as_deref(), &args.settings)?;
+    if let Some(dir) = args.fixture_server.clone() {
+        config.host = format!("http://{}", fixture::serve(dir)?);
     }
+    Ok(config)
+}
 
-    let config: Config = serde_json::from_reader(std::fs::File::open(config)?)?;
+fn backup(args: &BackupArgs, config: &Config) -> Result<()> {
+    let path = args.path.clone().unwrap_or_else(default_path);
+    std::fs::create_dir_all(&path)?;
+    let (namespace, meta_path) = if args.fixture_server.is_some() {
+        let namespace = fixture::NAMESPACE.to_owned();
+        let meta_path = path.join(format!("metadata-{namespace}.json"));
+        (namespace, meta_path)
+    } else {
+        let namespace = config.namespace();
+        let meta_path = store::metadata_path(&path, &namespace)?;
+        (namespace, meta_path)
+    };
 
-    let h2_client = reqwest::Client::new();
+    let t_now = OffsetDateTime::now_utc();
+    let run_name = match config.run_naming {
+        RunNaming::Timestamp => Some(
+            t_now
+                .to_offset(config.timezone.offset()?)
+                .format(&time::format_description::well_known::Iso8601::DATE_TIME)?,
+        ),
+        // Numbers are taken once directories are created, as other runs may take them meanwhile.
+        RunNaming::Sequence => None,
+    };
+    // Name and path of the run directory, once created.
+    let run_dir = OnceCell::new();
+
+    let mut client = match config.protocol {
+        Protocol::Auto => reqwest::Client::builder(),
+        Protocol::Http1 => reqwest::Client::builder().http1_only(),
+        Protocol::Http2 => reqwest::Client::builder().http2_prior_knowledge(),
+    }
+    .default_headers(net::auth_headers(&config.auth)?)
+    // Binding to an unspecified address of a version only connects to addresses of that version.
+    .local_address(
+        config
+            .local_address
+            .or_else(|| config.ip_family.local_address()),
+    );
+    if let Some(interface) = &config.interface {
+        #[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+        {
+            client = client.interface(interface);
+        }
+        #[cfg(not(any(target_os = "android", target_os = "linux", target_os = "macos")))]
+        anyhow::bail!("binding to interface {interface} isn't supported on this platform");
+    }
+    for (host, ip) in &config.resolve {
+        // Port zero stands for the port of the URL.
+        client = client.resolve(host, SocketAddr::new(*ip, 0));
+    }
+    let client = client.build()?;
     let limit = Cell::new((0usize, Instant::now()));
-    let main_meta = RefCell::new(
+    let tuner = Tuner::new(config.limit, config.adaptive);
+    let main_meta: RefCell<MainMetadata> = RefCell::new(
         std::fs::File::open(&meta_path)
             .ok()
             .and_then(|file| serde_json::from_reader(file).ok())
             .unwrap_or_default(),
     );
 
+    let quota = Quota::new(
+        t_now.to_offset(config.timezone.offset()?).date(),
+        config.max_requests_per_day,
+        config.max_bytes_per_day,
+        main_meta.borrow().daily.as_ref(),
+    );
+    let (report, render) = Reporter::new(args.progress_events);
+    // Runs of other targets may be in progress, and their documents aren't backed up by this run.
+    for dir in store::incomplete_runs_of(&path, &namespace)? {
+        if args.quarantine_incomplete {
+            let quarantine = path.join(QUARANTINE_DIR);
+            std::fs::create_dir_all(&quarantine)?;
+            std::fs::rename(&dir, quarantine.join(dir.file_name().unwrap_or_default()))?;
+            report.warn(format_args!("quarantined incomplete run {}", dir.display()));
+        } else {
+            // Documents missing from it weren't tracked, so they are backed up by this run.
+            report.warn(format_args!(
+                "run {} is incomplete, its missing documents are backed up again",
+                dir.display()
+            ));
+        }
+    }
+    // Otherwise the run directory is created once there's something to store.
+    let raw_path = if config.debug.dump_responses {
+        let (_, backup_path) = create_run_dir(&run_dir, &path, run_name.as_deref(), &namespace)?;
+        let raw_path = backup_path.join("raw");
+        std::fs::create_dir_all(&raw_path)?;
+        Some(raw_path)
+    } else {
+        None
+    };
+    let usage = Usage::new();
+    let chaos = Chaos::new(args.chaos.unwrap_or_default());
+    let budget = Budget::new(config.abort_after_errors, config.abort_error_rate);
+    let breaker = Breaker::new(
+        config.breaker_failures,
+        config
+            .breaker_cooldown
+            .map_or(breaker::DEFAULT_COOLDOWN, |age| age.0.unsigned_abs()),
+    );
+    let cache = Cache::new(
+        // Listings of different targets may share endpoint paths.
+        path.join(CACHE_DIR).join(&namespace),
+        std::time::Duration::from_secs(config.cache_ttl),
+    );
+
+    let cancel = CancellationToken::new();
     let cx = Context {
-        config: &config,
-        h2_client: &h2_client,
+        config,
+        client: &client,
         limit: &limit,
+        tuner: &tuner,
         meta: &main_meta,
+        report: &report,
+        cache: &cache,
+        budget: &budget,
+        breaker: &breaker,
+        chaos: &chaos,
+        usage: &usage,
+        quota: &quota,
+        cancel: &cancel,
+        raw_dir: raw_path.as_deref(),
+        force: args.force,
     };
 
     let mut rt = tokio::runtime::Builder::new_current_thread();
     rt.enable_all();
     let rt = rt.build()?;
 
-    rt.block_on(async {
+    // Documents saved in the run, by id.
+    let index = RefCell::new(BTreeMap::<i64, IndexEntry>::new());
+    // Whether nothing changed since the previous run.
+    let noop = Cell::new(false);
+    // Whether the daily quota was used up before anything could be downloaded.
+    let deferred = Cell::new(false);
+    let defer = || {
+        cx.report
+            .warn("daily quota used up, leaving the run for later, skipping the run directory");
+        noop.set(true);
+        deferred.set(true);
+        Ok(())
+    };
+    let run = async {
+        if cx.quota.exhausted() {
+            return defer();
+        }
         let repos = net::repos(cx).await?;
-        for chunk in repos.chunks(16) {
-            cx.meta
-                .borrow_mut()
-                .books
-                .extend(repos.iter().cloned().map(|r| (r.id, r)));
-            let _ = futures::future::join_all(chunk.iter().map(|repo| async {
-                let metas = net::doc_metas(cx, repo).await?;
-                let backup_path = &backup_path;
-                for meta_chunk in metas.chunks(16) {
-                    let _ = futures::future::join_all(
-                        meta_chunk
-                            .iter()
-                            .filter(|m| cx.meta.borrow().needs_backup(m))
-                            .cloned()
-                            .map(|m| async move {
-                                let doc = net::doc(cx, m.clone()).await.inspect_err(|err| {
-                                    eprintln!("error obtaining document: {}", err)
-                                })?;
-                                let mut file = tokio::fs::File::create_new(
-                                    backup_path.join(format!("doc{}.json", m.raw.id)),
-                                )
-                                .await?;
-                                file.write_all(&serde_json::to_vec_pretty(&doc)?).await?;
-                                cx.meta.borrow_mut().track_backup(&m);
-                                Result::<_, anyhow::Error>::Ok(())
-                            }),
+        let new_repos = repos
+            .iter()
+            .filter(|repo| !cx.meta.borrow().books.contains_key(&repo.id))
+            .count();
+        for repo in &repos {
+            if let Some(old) = cx.meta.borrow_mut().track_repo(repo, t_now) {
+                cx.report.warn(format_args!(
+                    "repository {} changed from {} ({}) to {} ({})",
+                    repo.id, old.name, old.slug, repo.name, repo.slug
+                ));
+            }
+        }
+        let unchanged = |repo: &Repo| {
+            cx.config.skip_unchanged_repos && !cx.force && cx.meta.borrow().synced(repo)
+        };
+        let to_list: Vec<_> = repos.iter().filter(|repo| !unchanged(repo)).collect();
+        if to_list.len() < repos.len() {
+            cx.report.warn(format_args!(
+                "skipping listing {} unchanged repositories",
+                repos.len() - to_list.len()
+            ));
+        }
+        // Repositories listed successfully, which are synced if none of their documents fail or are
+        // skipped.
+        let mut listed = Vec::with_capacity(to_list.len());
+        let mut listings = Vec::with_capacity(to_list.len());
+        for chunk in to_list.chunks(16) {
+            if cx.quota.exhausted() {
+                break;
+            }
+            listings.extend(
+                chunk
+                    .iter()
+                    .zip(
+                        futures::future::join_all(
+                            chunk.iter().map(|repo| net::doc_metas(cx, repo)),
+                        )
+                        .await,
                     )
-                    .await;
+                    .filter_map(|(repo, res)| {
+                        cx.budget.record(res.is_ok());
+                        res.inspect_err(|err| {
+                            cx.report
+                                .error(format_args!("obtaining document metadatas: {}", err))
+                        })
+                        .ok()
+                        .inspect(|metas| {
+                            listed.push(*repo);
+                            if let Some(missing) =
+                                cx.meta.borrow_mut().track_listing(repo, metas.len())
+                            {
+                                cx.report.warn(format_args!(
+                                    "repository {} ({}) lists {} fewer documents than it has",
+                                    repo.id, repo.slug, missing
+                                ));
+                            }
+                        })
+                    })
+                    .map(|mut metas| {
+                        metas.retain(|m| cx.config.includes_status(m.raw.status));
+                        metas
+                    }),
+            );
+        }
+
+        // Repositories with documents not backed up, listed again by later runs so they are backed
+        // up once possible, like after being removed from the skip list.
+        let failed = RefCell::new(HashSet::new());
+        // All documents to be backed up, as a queue consumed by download workers.
+        let mut queue: Vec<&DocMeta<'_>> = listings
+            .iter()
+            .flatten()
+            .filter(|m| {
+                let meta = cx.meta.borrow();
+                if meta.skip_list.contains_key(&m.raw.id) {
+                    failed.borrow_mut().insert(m.repo.id);
+                    return false;
                 }
-                Result::<_, anyhow::Error>::Ok(())
-            }))
-            .await;
+                cx.force || meta.needs_backup(m)
+            })
+            .collect();
+        match cx.config.strategy {
+            Strategy::NewestFirst => queue.sort_by_key(|m| std::cmp::Reverse(m.raw.updated_at)),
+            Strategy::OldestFirst => queue.sort_by_key(|m| m.raw.updated_at),
+            Strategy::SmallestFirst => queue.sort_by_key(|m| m.raw.word_count),
+            Strategy::RepoOrder => {}
+        }
+        if cx.quota.exhausted() {
+            return defer();
+        }
+        if queue.is_empty()
+            && new_repos == 0
+            && listed.len() == to_list.len()
+            && cx.raw_dir.is_none()
+            && !(cx.config.toc || cx.config.statistics || cx.config.members)
+        {
+            for repo in listed {
+                if !failed.borrow().contains(&repo.id) {
+                    cx.meta.borrow_mut().track_synced(repo);
+                }
+            }
+            cx.report
+                .warn("nothing changed since the previous run, skipping the run directory");
+            noop.set(true);
+            return Ok(());
+        }
+        // Checked before creating the run directory, so runs failing it leave nothing behind.
+        space::preflight(&path, queue.iter().copied())?;
+        let (_, backup_path) = create_run_dir(&run_dir, &path, run_name.as_deref(), &namespace)?;
+        cx.report.emit(Event::RunStarted { path: backup_path });
+        for metas in &listings {
+            if let Some(repo) = metas.first().map(|m| m.repo) {
+                cx.report.emit(Event::RepoStarted {
+                    id: repo.id,
+                    slug: &repo.slug,
+                    docs: queue.iter().filter(|m| m.repo.id == repo.id).count(),
+                });
+            }
+        }
+        cx.budget.check()?;
+
+        let mut queue = queue.into_iter();
+        let mut workers = FuturesUnordered::new();
+        loop {
+            while workers.len() < cx.tuner.chunk_size()
+                && !cx.budget.exceeded()
+                && !cx.quota.exhausted()
+                && !cx.cancel.is_cancelled()
+            {
+                let Some(m) = queue.next() else {
+                    break;
+                };
+                let index = &index;
+                let failed = &failed;
+                workers.push(async move {
+                    match backup_doc(cx, backup_path, m).await {
+                        Ok(None) => cx.budget.record(true),
+                        Ok(Some((bytes, entry))) => {
+                            index.borrow_mut().insert(m.raw.id, entry);
+                            cx.budget.record(true);
+                            cx.report.emit(Event::DocSaved {
+                                id: m.raw.id,
+                                repo: m.repo.id,
+                                bytes,
+                            });
+                        }
+                        Err(err) => match err.downcast::<TooLarge>() {
+                            Ok(err) => {
+                                failed.borrow_mut().insert(m.repo.id);
+                                cx.budget.record(true);
+                                cx.report
+                                    .warn(format_args!("skipping document {}: {}", m.raw.id, err));
+                                cx.meta
+                                    .borrow_mut()
+                                    .skipped
+                                    .insert(m.raw.id, err.to_string());
+                                cx.meta.borrow_mut().failures.remove(&m.raw.id);
+                            }
+                            Err(err) if cx.config.skip_forbidden && net::forbidden(&err) => {
+                                failed.borrow_mut().insert(m.repo.id);
+                                cx.budget.record(true);
+                                cx.report.warn(format_args!(
+                                    "adding document {} to the skip list: {}",
+                                    m.raw.id, err
+                                ));
+                                let mut meta = cx.meta.borrow_mut();
+                                meta.skip_list.insert(m.raw.id, err.to_string());
+                                meta.failures.remove(&m.raw.id);
+                            }
+                            Err(err) if err.is::<Cancelled>() => {
+                                failed.borrow_mut().insert(m.repo.id);
+                            }
+                            Err(err) => {
+                                failed.borrow_mut().insert(m.repo.id);
+                                cx.meta.borrow_mut().track_failure(
+                                    m.raw.id,
+                                    err.to_string(),
+                                    OffsetDateTime::now_utc(),
+                                );
+                                cx.budget.record(false);
+                                cx.report.error(format_args!(
+                                    "backing up document {}: {}",
+                                    m.raw.id, err
+                                ));
+                            }
+                        },
+                    }
+                });
+            }
+            if workers.next().await.is_none() {
+                break;
+            }
+        }
+        drop(workers);
+        if cx.quota.exhausted() && queue.len() > 0 {
+            cx.report.warn(format_args!(
+                "daily quota used up, {} documents are left for later runs",
+                queue.len()
+            ));
+        }
+        // Documents not attempted are missing as well.
+        failed.borrow_mut().extend(queue.map(|m| m.repo.id));
+        for repo in listed {
+            if !failed.borrow().contains(&repo.id) {
+                cx.meta.borrow_mut().track_synced(repo);
+            }
+        }
+        std::fs::write(
+            backup_path.join(INDEX_FILE),
+            serde_json::to_vec_pretty(&index)?,
+        )?;
+        if cx.cancel.is_cancelled() {
+            anyhow::bail!("run cancelled, remaining documents are left for later runs");
+        }
+        cx.budget.check()?;
+
+        // Left for later runs along with the documents.
+        if cx.config.toc && !cx.quota.exhausted() {
+            for chunk in repos.chunks(16) {
+                for (repo, res) in chunk.iter().zip(
+                    futures::future::join_all(chunk.iter().map(|repo| net::toc(cx, repo))).await,
+                ) {
+                    match res {
+                        Ok(toc) => std::fs::write(
+                            backup_path.join(format!("toc{}.json", repo.id)),
+                            serde_json::to_vec_pretty(&toc)?,
+                        )?,
+                        Err(err) => cx.report.error(format_args!(
+                            "obtaining table of contents of repository {}: {}",
+                            repo.id, err
+                        )),
+                    }
+                }
+            }
+        }
+
+        if cx.config.statistics && !cx.quota.exhausted() {
+            if matches!(cx.config.target.ty, TargetType::Group) {
+                let stats = net::statistics(cx).await?;
+                std::fs::write(
+                    backup_path.join("statistics.json"),
+                    serde_json::to_vec_pretty(&stats)?,
+                )?;
+            } else {
+                cx.report
+                    .warn("statistics are only available for groups, skipping");
+            }
         }
+
+        if cx.config.members && !cx.quota.exhausted() {
+            if matches!(cx.config.target.ty, TargetType::Group) {
+                match net::members(cx).await {
+                    Ok(members) => std::fs::write(
+                        backup_path.join("members.json"),
+                        serde_json::to_vec_pretty(&members)?,
+                    )?,
+                    Err(err) => cx
+                        .report
+                        .error(format_args!("obtaining group members: {}", err)),
+                }
+            } else {
+                cx.report
+                    .warn("members are only available for groups, skipping");
+            }
+        }
+
         Result::<_, anyhow::Error>::Ok(())
-    })?;
+    };
+    // Cancels the run once out of time, or finishes along with the run.
+    let deadline = async {
+        if let Some(max) = config.max_run_time {
+            futures::future::select(
+                pin!(tokio::time::sleep(max.0.unsigned_abs())),
+                pin!(cancel.cancelled()),
+            )
+            .await;
+            cancel.cancel();
+        }
+    };
+    let (res, (), ()) = rt.block_on(futures::future::join3(
+        async {
+            let res = run.await;
+            cancel.cancel();
+            cx.report.warn(cx.usage.summary(cx.config.limit));
+            if args.watch.is_some() {
+                for (id, entry) in index.borrow().iter() {
+                    cx.report.warn(format_args!(
+                        "{} document {} ({}/{}): {}",
+                        if entry.new { "new" } else { "changed" },
+                        id,
+                        entry.repo,
+                        entry.slug,
+                        entry.title
+                    ));
+                }
+            }
+            if let Some(url) = &cx.config.report_url {
+                let (docs, bytes) = cx.report.totals();
+                let index = index.take();
+                let summary = RunSummary {
+                    target: &namespace,
+                    dir: run_dir.get().map(|(name, _)| name.as_str()),
+                    started: t_now,
+                    docs,
+                    bytes,
+                    changes: index
+                        .iter()
+                        .take(MAX_CHANGES)
+                        .map(|(&id, entry)| Change {
+                            id,
+                            title: &entry.title,
+                            new: entry.new,
+                        })
+                        .collect(),
+                    more_changes: index.len().saturating_sub(MAX_CHANGES),
+                    noop: noop.get(),
+                    error: res.as_ref().err().map(ToString::to_string),
+                };
+                if let Err(err) =
+                    net::post_summary(url, cx.config.report_token.as_ref(), &summary).await
+                {
+                    cx.report
+                        .warn(format_args!("reporting the run to {}: {}", url, err));
+                }
+            }
+            cx.report.finish();
+            res
+        },
+        render,
+        deadline,
+    ));
 
+    let run_dir = run_dir.into_inner();
+    main_meta.borrow_mut().daily = Some(quota.usage());
+    main_meta.borrow_mut().runs.push(RunItem {
+        // Runs failing before storing anything don't create their directories either.
+        dir: run_dir.as_ref().map(|(name, _)| name.clone()),
+        started: BackupTime(t_now),
+        noop: noop.get(),
+        error: res.as_ref().err().map(ToString::to_string),
+    });
+    if res.is_ok() && !deferred.get() {
+        main_meta.borrow_mut().last_success = Some(BackupTime(t_now));
+    }
+    // Documents backed up before an abort are still tracked.
     std::fs::write(meta_path, serde_json::to_vec_pretty(&main_meta)?)?;
-    Ok(())
+    if let (Ok(()), Some((_, backup_path))) = (&res, &run_dir) {
+        std::fs::remove_file(backup_path.join(INCOMPLETE_MARKER))?;
+    }
+    res
+}
+
+/// Creates the run directory if not yet, with the given name or the next sequence number.
+fn create_run_dir<'a>(
+    run_dir: &'a OnceCell<(String, PathBuf)>,
+    path: &Path,
+    name: Option<&str>,
+    namespace: &str,
+) -> std::io::Result<&'a (String, PathBuf)> {
+    if let Some(dir) = run_dir.get() {
+        return Ok(dir);
+    }
+    let name = match name {
+        Some(name) => {
+            store::create_run_dir(&path.join(name), namespace)?;
+            name.to_owned()
+        }
+        None => store::create_next_run_dir(path, &path.join(QUARANTINE_DIR), namespace)?,
+    };
+    let backup_path = path.join(&name);
+    Ok(run_dir.get_or_init(|| (name, backup_path)))
+}
+
+/// Backs up the document of the given metadata into the run directory.
+///
+/// Returns the number of bytes written.
+async fn backup_doc(
+    cx: Context<'_>,
+    path: &Path,
+    meta: &DocMeta<'_>,
+) -> Result<Option<(usize, IndexEntry)>> {
+    let mut doc = net::doc(cx, meta.clone()).await?;
+    let mut suspicions = Suspicion::check(&doc, meta.raw.word_count);
+    if !suspicions.is_empty() {
+        cx.report.warn(format_args!(
+            "document {} seems truncated ({:?}), retrying",
+            meta.raw.id, suspicions
+        ));
+        doc = net::doc(cx, meta.clone()).await?;
+        suspicions = Suspicion::check(&doc, meta.raw.word_count);
+    }
+    // Forced backups of identical documents would only store duplicates.
+    if cx.force && cx.meta.borrow().unchanged(meta, &doc) {
+        return Ok(None);
+    }
+    let bytes = if cx.config.compact {
+        serde_json::to_vec(&doc)?
+    } else {
+        serde_json::to_vec_pretty(&doc)?
+    };
+    let new = !cx.meta.borrow().items.contains_key(&meta.raw.id);
+    let name = format!("doc{}.json", meta.raw.id);
+    let mut file = tokio::fs::File::create_new(path.join(&name)).await?;
+    file.write_all(&bytes).await?;
+    if let Some(old) = cx.meta.borrow_mut().track_backup(meta, &doc, suspicions) {
+        cx.report.warn(format_args!(
+            "slug of document {} changed from {} to {}",
+            meta.raw.id, old, meta.raw.slug
+        ));
+    }
+    Ok(Some((
+        bytes.len(),
+        IndexEntry {
+            repo: meta.repo.slug.clone(),
+            slug: meta.raw.slug.clone(),
+            title: doc.title,
+            path: name,
+            updated_at: BackupTime(meta.raw.updated_at),
+            new,
+        },
+    )))
 }
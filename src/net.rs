@@ -1,17 +1,46 @@
 use std::{
+    collections::HashSet,
+    fmt::Display,
+    future::Future,
+    pin::pin,
     rc::Rc,
     time::{Duration, Instant},
 };
 
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{bail, Result};
+use futures::future::Either;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE},
+    RequestBuilder, Response, StatusCode, Url,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{Map, Value};
 
-use crate::{Context, Doc, DocMeta, RawDocMeta, Repo};
+use crate::{
+    cache,
+    chaos::{self, Failure},
+    config::{AuthConfig, TargetType},
+    report::RunSummary,
+    Context, Doc, DocMeta, Group, RawDocMeta, Repo, Token,
+};
 
 const TOKEN_KEY: &str = "X-Auth-Token";
 const QUERY_LIMIT: (&str, &str) = ("limit", "100");
+/// Number of items in a full page of listings, as requested by [`QUERY_LIMIT`].
+const PAGE_SIZE: usize = 100;
+/// Pages after which a listing stops, in case the server ignores offsets.
+const MAX_PAGES: usize = 1000;
 const USER_AGENT_KEY: &str = "User-Agent";
 const USER_AGENT_VALUE: &str = "User-Agent Mozilla/5.0";
+/// Timeout of posting the summary of a run.
+const SUMMARY_TIMEOUT: Duration = Duration::from_secs(30);
+/// Statistics endpoints of a group, relative to the group path.
+const STATISTICS: [&str; 4] = [
+    "statistics",
+    "statistics/members",
+    "statistics/books",
+    "statistics/docs",
+];
 
 #[derive(Deserialize)]
 struct ResponseObj<T> {
@@ -19,78 +48,368 @@ struct ResponseObj<T> {
 }
 
 /// Gets repositories of the target.
+///
+/// Repositories of groups the target user joined are included if configured,
+/// de-duplicated by id.
 pub async fn repos(cx: Context<'_>) -> Result<Vec<Repo>> {
-    cool(&cx).await;
+    let mut repos = repos_of(&cx, cx.uri_path()).await?;
+    if cx.config.target.include_groups && matches!(cx.config.target.ty, TargetType::User) {
+        for group in groups(&cx).await? {
+            match repos_of(&cx, format_args!("/groups/{}", group.login)).await {
+                Ok(r) => repos.extend(r),
+                Err(err) => cx.report.error(format_args!(
+                    "obtaining repositories of group {}: {}",
+                    group.login, err
+                )),
+            }
+        }
+        let mut seen = HashSet::new();
+        repos.retain(|r| seen.insert(r.id));
+    }
+    Ok(repos)
+}
 
-    let url = cx.url(format!("/api/v2{}/repos", cx.uri_path()))?;
-    cx.h2_client
-        .get(url)
-        .header(TOKEN_KEY, &cx.config.token)
-        .header(USER_AGENT_KEY, USER_AGENT_VALUE)
-        .query(&[QUERY_LIMIT])
-        .send()
-        .await?
-        .json::<ResponseObj<Vec<Repo>>>()
-        .await
-        .map(|obj| obj.data)
-        .map_err(Into::into)
+/// Gets groups the target user joined.
+async fn groups(cx: &Context<'_>) -> Result<Vec<Group>> {
+    let url = cx.url(format!("/api/v2{}/groups", cx.uri_path()))?;
+    let key = url.path().to_owned();
+    listing(cx, url, &key).await
+}
+
+/// Gets repositories of the given user or group path, like `/groups/subit`.
+async fn repos_of(cx: &Context<'_>, path: impl Display) -> Result<Vec<Repo>> {
+    let url = cx.url(format!("/api/v2{}/repos", path))?;
+    let key = url.path().to_owned();
+    listing(cx, url, &key).await
+}
+
+/// Gets statistics of the target group, keyed by endpoint.
+///
+/// Endpoints the token isn't allowed to access are skipped.
+pub async fn statistics(cx: Context<'_>) -> Result<Map<String, Value>> {
+    let mut stats = Map::new();
+    for endpoint in STATISTICS {
+        let url = cx.url(format!("/api/v2{}/{}", cx.uri_path(), endpoint))?;
+        match get::<Value>(&cx, cx.client.get(url)).await {
+            Ok(data) => {
+                stats.insert(endpoint.to_owned(), data);
+            }
+            Err(err) => cx
+                .report
+                .error(format_args!("obtaining {}: {}", endpoint, err)),
+        }
+    }
+    Ok(stats)
+}
+
+/// Gets members of the target group, with their roles.
+pub async fn members(cx: Context<'_>) -> Result<Value> {
+    let url = cx.url(format!("/api/v2{}/users", cx.uri_path()))?;
+    get(&cx, cx.client.get(url)).await
+}
+
+/// Gets the table of contents of the given [`Repo`].
+pub async fn toc(cx: Context<'_>, repo: &Repo) -> Result<Value> {
+    let url = cx.url(format!("/api/v2/repos/{}/toc", repo.id))?;
+    get(&cx, cx.client.get(url)).await
 }
 
 /// Gets document details of the given id and [`Repo`].
 pub async fn doc(cx: Context<'_>, meta: DocMeta<'_>) -> Result<Doc> {
-    cool(&cx).await;
-
     let url = cx.url(format!(
         "/api/v2/repos/{}/docs/{}",
         meta.repo.id, meta.raw.id
     ))?;
-    cx.h2_client
-        .get(url)
-        .header(TOKEN_KEY, &cx.config.token)
-        .header(USER_AGENT_KEY, USER_AGENT_VALUE)
-        .send()
-        .await?
-        .json::<ResponseObj<Doc>>()
-        .await
-        .map(|obj| obj.data)
-        .map_err(Into::into)
+    let max = cx.config.max_doc_body_mb.map(|mb| mb * 1024 * 1024);
+    let res = send(&cx, cx.client.get(url)).await?;
+    // Large bodies are only read as far as the limit if they are to be skipped.
+    let bytes = body(&cx, res, max.filter(|_| cx.config.skip_large_docs)).await?;
+    if let Some(max) = max.filter(|max| bytes.len() as u64 > *max) {
+        cx.report.warn(format_args!(
+            "document {} is larger than {} MiB",
+            meta.raw.id,
+            max / (1024 * 1024)
+        ));
+    }
+    Ok(serde_json::from_slice::<ResponseObj<Doc>>(&bytes)?.data)
+}
+
+/// Error of a response body larger than the limit.
+#[derive(Debug)]
+pub struct TooLarge {
+    /// The limit in bytes.
+    pub limit: u64,
+}
+
+impl Display for TooLarge {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "body larger than {} MiB", self.limit / (1024 * 1024))
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+/// Whether the error is of a request the token is forbidden to make.
+pub fn forbidden(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        == Some(StatusCode::FORBIDDEN)
+}
+
+/// Error of an operation cancelled along with the run.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl Display for Cancelled {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Runs the future until the run is cancelled, failing with [`Cancelled`] then.
+async fn cancellable<F: Future>(cx: &Context<'_>, f: F) -> Result<F::Output> {
+    match futures::future::select(pin!(f), pin!(cx.cancel.cancelled())).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => bail!(Cancelled),
+    }
 }
 
 /// Gets document metadatas of the given [`Repo`].
 pub async fn doc_metas<'repo>(cx: Context<'_>, repo: &'repo Repo) -> Result<Vec<DocMeta<'repo>>> {
-    cool(&cx).await;
-
     let url = cx.url(format!("/api/v2/repos/{}/docs", repo.id))?;
-    cx.h2_client
-        .get(url)
+    // Listings of an updated repository are never taken from the cache.
+    let key = format!("{}@{}", url.path(), repo.updated_at.unix_timestamp());
+    listing::<RawDocMeta>(&cx, url, &key).await.map(|metas| {
+        metas
+            .into_iter()
+            .map(|meta| DocMeta {
+                repo,
+                raw: Rc::new(meta),
+            })
+            .collect()
+    })
+}
+
+/// Gets a listing of the given URL page by page, through the response cache.
+async fn listing<T: DeserializeOwned>(cx: &Context<'_>, url: Url, key: &str) -> Result<Vec<T>> {
+    let mut items = vec![];
+    for page in 0..MAX_PAGES {
+        let offset = page * PAGE_SIZE;
+        // The first page is requested without an offset, as before pagination.
+        let (req, key) = if offset == 0 {
+            (
+                cx.client.get(url.clone()).query(&[QUERY_LIMIT]),
+                key.to_owned(),
+            )
+        } else {
+            (
+                cx.client
+                    .get(url.clone())
+                    .query(&[QUERY_LIMIT, ("offset", &offset.to_string())]),
+                format!("{key}&offset={offset}"),
+            )
+        };
+        let data = match cx
+            .cache
+            .get(&key)
+            .and_then(|bytes| serde_json::from_slice::<ResponseObj<Vec<T>>>(&bytes).ok())
+        {
+            Some(obj) => obj.data,
+            None => {
+                let bytes = body(cx, send(cx, req).await?, None).await?;
+                let obj = serde_json::from_slice::<ResponseObj<Vec<T>>>(&bytes)?;
+                if let Err(err) = cx.cache.put(&key, &bytes) {
+                    cx.report.warn(format_args!("error writing cache: {}", err));
+                }
+                obj.data
+            }
+        };
+        let len = data.len();
+        items.extend(data);
+        if len < PAGE_SIZE {
+            return Ok(items);
+        }
+    }
+    cx.report.warn(format_args!(
+        "listing of {} stopped after {} pages",
+        url, MAX_PAGES
+    ));
+    Ok(items)
+}
+
+/// Gets the data of the response to the given request.
+async fn get<T: DeserializeOwned>(cx: &Context<'_>, req: RequestBuilder) -> Result<T> {
+    let bytes = body(cx, send(cx, req).await?, None).await?;
+    Ok(serde_json::from_slice::<ResponseObj<T>>(&bytes)?.data)
+}
+
+/// Reads the body of the response, dumping it into the raw directory if configured.
+///
+/// Fails with [`TooLarge`] as soon as the body is known to exceed the given limit.
+async fn body(cx: &Context<'_>, mut res: Response, limit: Option<u64>) -> Result<Vec<u8>> {
+    let url = res.url().clone();
+    let exceeds = |len: u64| limit.is_some_and(|limit| len > limit);
+    if res.content_length().is_some_and(exceeds) {
+        bail!(TooLarge {
+            limit: limit.unwrap_or_default()
+        });
+    }
+    let mut bytes = Vec::with_capacity(res.content_length().unwrap_or_default() as usize);
+    while let Some(chunk) = cancellable(cx, res.chunk()).await?? {
+        cx.quota.download(chunk.len());
+        bytes.extend_from_slice(&chunk);
+        if exceeds(bytes.len() as u64) {
+            bail!(TooLarge {
+                limit: limit.unwrap_or_default()
+            });
+        }
+    }
+    if let Some(dir) = cx.raw_dir {
+        let key = url.query().map_or_else(
+            || url.path().to_owned(),
+            |query| format!("{}?{}", url.path(), query),
+        );
+        let path = dir.join(format!("{}.json", cache::sanitize(&key)));
+        let text = cx.config.token.redact(&String::from_utf8_lossy(&bytes));
+        if let Err(err) = std::fs::write(&path, text) {
+            cx.report.warn(format_args!(
+                "error dumping response to {}: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+    Ok(bytes)
+}
+
+/// Posts the summary of a run to the given URL.
+///
+/// A separate client is used, so the Yuque token and extra authentication never leak to it.
+pub async fn post_summary(
+    url: &str,
+    token: Option<&Token>,
+    summary: &RunSummary<'_>,
+) -> Result<()> {
+    let mut req = reqwest::Client::new()
+        .post(url)
+        .timeout(SUMMARY_TIMEOUT)
+        .header(USER_AGENT_KEY, USER_AGENT_VALUE)
+        .json(summary);
+    if let Some(token) = token {
+        req = req.header(
+            AUTHORIZATION,
+            sensitive(HeaderValue::try_from(token.bearer())?),
+        );
+    }
+    req.send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// Builds headers for extra authentication, running the login command if any.
+pub fn auth_headers(auth: &AuthConfig) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &auth.headers {
+        headers.insert(HeaderName::try_from(name)?, sensitive(value.try_into()?));
+    }
+    if let Some(cookie) = &auth.cookie {
+        headers.insert(COOKIE, sensitive(cookie.try_into()?));
+    }
+    if let Some(command) = &auth.login_command {
+        let output = std::process::Command::new("sh")
+            .args(["-c", command])
+            .stderr(std::process::Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            bail!("login command failed with {}", output.status);
+        }
+        for line in String::from_utf8(output.stdout)?.lines() {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            headers.insert(
+                HeaderName::try_from(name.trim())?,
+                sensitive(HeaderValue::try_from(value.trim())?),
+            );
+        }
+    }
+    Ok(headers)
+}
+
+#[inline]
+fn sensitive(mut value: HeaderValue) -> HeaderValue {
+    value.set_sensitive(true);
+    value
+}
+
+/// Sends the request with authorization, reporting its latency to the tuner.
+async fn send(cx: &Context<'_>, req: RequestBuilder) -> Result<Response> {
+    let req = req
         .header(TOKEN_KEY, &cx.config.token)
         .header(USER_AGENT_KEY, USER_AGENT_VALUE)
-        .query(&[QUERY_LIMIT])
-        .send()
-        .await?
-        .json::<ResponseObj<Vec<RawDocMeta>>>()
-        .await
-        .map(|obj| {
-            obj.data
-                .into_iter()
-                .map(|meta| DocMeta {
-                    repo,
-                    raw: Rc::new(meta),
-                })
-                .collect()
-        })
-        .map_err(Into::into)
+        .build()?;
+    let host = req.url().host_str().unwrap_or_default().to_owned();
+    cancellable(cx, cx.breaker.wait(&host)).await?;
+    cancellable(cx, cool(cx)).await?;
+
+    if let Some(failure) = cx.chaos.roll() {
+        match failure {
+            Failure::TooManyRequests => cx
+                .tuner
+                .observe(Duration::ZERO, StatusCode::TOO_MANY_REQUESTS),
+            Failure::Timeout => cancellable(cx, tokio::time::sleep(chaos::TIMEOUT)).await?,
+            Failure::ServerError => {}
+        }
+        trip(cx, &host, false);
+        bail!("injected failure: {}", failure);
+    }
+
+    cx.usage.request();
+    cx.quota.request();
+    let start = Instant::now();
+    let res = cancellable(cx, cx.client.execute(req)).await?;
+    trip(
+        cx,
+        &host,
+        res.as_ref().is_ok_and(|res| {
+            !res.status().is_server_error() && res.status() != StatusCode::TOO_MANY_REQUESTS
+        }),
+    );
+    let res = res?;
+    cx.tuner.observe(start.elapsed(), res.status());
+    res.error_for_status().map_err(Into::into)
+}
+
+/// Records a request to the host in its circuit breaker.
+#[inline]
+fn trip(cx: &Context<'_>, host: &str, ok: bool) {
+    if cx.breaker.record(host, ok) {
+        cx.report.warn(format_args!(
+            "requests to {} keep failing, pausing them",
+            host
+        ));
+    }
 }
 
 #[inline]
 async fn cool(cx: &Context<'_>) {
-    let (requests, i) = cx.limit.get();
-    if requests < cx.config.limit {
-        cx.limit.set((requests + 1, i));
-    } else {
-        tokio::time::sleep_until(tokio::time::Instant::from_std(i + Duration::from_secs(1))).await;
-        if cx.limit.get().1 == i {
-            cx.limit.set((1, Instant::now()));
+    loop {
+        let (requests, i) = cx.limit.get();
+        if requests < cx.tuner.limit() {
+            cx.limit.set((requests + 1, i));
+            return;
+        }
+        let until = i + Duration::from_secs(1);
+        let now = Instant::now();
+        if now >= until {
+            cx.limit.set((1, now));
+            return;
         }
+        cx.usage.stall(i, until - now);
+        // Waiters check the window again after waking, as others may have taken it.
+        tokio::time::sleep_until(tokio::time::Instant::from_std(until)).await;
     }
 }
@@ -0,0 +1,72 @@
+use std::cell::Cell;
+
+use serde::{Deserialize, Serialize};
+use time::Date;
+
+/// Daily quotas of API requests and downloaded bytes, carried across runs of a day.
+#[derive(Debug)]
+pub struct Quota {
+    day: Date,
+    max_requests: Option<u64>,
+    max_bytes: Option<u64>,
+    requests: Cell<u64>,
+    bytes: Cell<u64>,
+}
+
+/// Usage counted against daily quotas, recorded in the metadata.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DailyUsage {
+    /// The day, like `2024-06-01`.
+    pub day: String,
+    pub requests: u64,
+    pub bytes: u64,
+}
+
+impl Quota {
+    /// Creates quotas of the given day, continuing the recorded usage if it's of the same day.
+    pub fn new(
+        day: Date,
+        max_requests: Option<u64>,
+        max_bytes: Option<u64>,
+        used: Option<&DailyUsage>,
+    ) -> Self {
+        let (requests, bytes) = used
+            .filter(|used| used.day == day.to_string())
+            .map_or((0, 0), |used| (used.requests, used.bytes));
+        Self {
+            day,
+            max_requests,
+            max_bytes,
+            requests: Cell::new(requests),
+            bytes: Cell::new(bytes),
+        }
+    }
+
+    /// Records a request sent.
+    #[inline]
+    pub fn request(&self) {
+        self.requests.set(self.requests.get() + 1);
+    }
+
+    /// Records bytes downloaded.
+    #[inline]
+    pub fn download(&self, bytes: usize) {
+        self.bytes.set(self.bytes.get() + bytes as u64);
+    }
+
+    /// Whether any of the quotas is used up.
+    pub fn exhausted(&self) -> bool {
+        self.max_requests
+            .is_some_and(|max| self.requests.get() >= max)
+            || self.max_bytes.is_some_and(|max| self.bytes.get() >= max)
+    }
+
+    /// Gets the usage of the day to record.
+    pub fn usage(&self) -> DailyUsage {
+        DailyUsage {
+            day: self.day.to_string(),
+            requests: self.requests.get(),
+            bytes: self.bytes.get(),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    cell::Cell,
+    fmt::Display,
+    io::{IsTerminal, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+/// A machine-readable progress event.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    RunStarted { path: &'a Path },
+    RepoStarted { id: i64, slug: &'a str, docs: usize },
+    DocSaved { id: i64, repo: i64, bytes: usize },
+    Error { message: String },
+    RunFinished { docs: usize, bytes: usize },
+}
+
+/// Summary of a run, reported to the configured URL.
+#[derive(Debug, Serialize)]
+pub struct RunSummary<'a> {
+    /// Namespace of the metadata of the target.
+    pub target: &'a str,
+    /// Name of the run directory, unless it wasn't created.
+    pub dir: Option<&'a str>,
+    #[serde(with = "time::serde::iso8601")]
+    pub started: OffsetDateTime,
+    pub docs: usize,
+    pub bytes: usize,
+    /// New or changed documents, up to [`MAX_CHANGES`] of them.
+    pub changes: Vec<Change<'a>>,
+    /// Number of further new or changed documents.
+    pub more_changes: usize,
+    /// Whether nothing changed since the previous run, so the run directory wasn't created.
+    pub noop: bool,
+    /// Error the run aborted with, if any.
+    pub error: Option<String>,
+}
+
+/// Maximum number of changes listed in a [`RunSummary`].
+pub const MAX_CHANGES: usize = 20;
+
+/// A new or changed document in a [`RunSummary`].
+#[derive(Debug, Serialize)]
+pub struct Change<'a> {
+    pub id: i64,
+    pub title: &'a str,
+    pub new: bool,
+}
+
+#[derive(Debug)]
+enum Message {
+    /// A line of human-readable output on stderr.
+    Line(String),
+    /// A progress event as a JSON line on stdout.
+    Event(String),
+    /// Content of the status area.
+    Status(String),
+    Finish,
+}
+
+/// Reporter of user-facing output of a run.
+///
+/// All output is funneled to a single rendering task, so lines of concurrent
+/// tasks never interleave.
+#[derive(Debug)]
+pub struct Reporter {
+    tx: UnboundedSender<Message>,
+    events: bool,
+    total: Cell<usize>,
+    docs: Cell<usize>,
+    bytes: Cell<usize>,
+}
+
+impl Reporter {
+    /// Creates a reporter and its rendering task, which finishes after [`Self::finish`].
+    ///
+    /// Progress events are emitted as JSON lines on stdout if `events` is enabled.
+    pub fn new(events: bool) -> (Self, impl std::future::Future<Output = ()>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (
+            Self {
+                tx,
+                events,
+                total: Cell::new(0),
+                docs: Cell::new(0),
+                bytes: Cell::new(0),
+            },
+            render(rx),
+        )
+    }
+
+    /// Reports an error, also emitted as an event.
+    pub fn error(&self, message: impl Display) {
+        let message = message.to_string();
+        self.send(Message::Line(format!("error {message}")));
+        self.emit(Event::Error { message });
+    }
+
+    /// Reports a warning.
+    #[inline]
+    pub fn warn(&self, message: impl Display) {
+        self.send(Message::Line(message.to_string()));
+    }
+
+    /// Emits the given event, updating the status area.
+    pub fn emit(&self, event: Event<'_>) {
+        match event {
+            Event::RepoStarted { docs, .. } => self.total.set(self.total.get() + docs),
+            Event::DocSaved { bytes, .. } => {
+                self.docs.set(self.docs.get() + 1);
+                self.bytes.set(self.bytes.get() + bytes);
+            }
+            _ => {}
+        }
+        if self.events {
+            if let Ok(line) = serde_json::to_string(&event) {
+                self.send(Message::Event(line));
+            }
+        }
+        self.send(Message::Status(format!(
+            "{}/{} documents saved",
+            self.docs.get(),
+            self.total.get()
+        )));
+    }
+
+    /// Gets the number and total size of documents saved so far.
+    #[inline]
+    pub fn totals(&self) -> (usize, usize) {
+        (self.docs.get(), self.bytes.get())
+    }
+
+    /// Emits the event of the finished run, and finishes the rendering task.
+    pub fn finish(&self) {
+        self.emit(Event::RunFinished {
+            docs: self.docs.get(),
+            bytes: self.bytes.get(),
+        });
+        self.send(Message::Finish);
+    }
+
+    #[inline]
+    fn send(&self, message: Message) {
+        let _ = self.tx.send(message);
+    }
+}
+
+/// Renders reported output until finished.
+///
+/// The status area is only drawn when stderr is a terminal.
+async fn render(mut rx: UnboundedReceiver<Message>) {
+    let status_area = std::io::stderr().is_terminal();
+    let mut status = String::new();
+    while let Some(message) = rx.recv().await {
+        let mut stderr = std::io::stderr().lock();
+        if status_area && !status.is_empty() {
+            let _ = write!(stderr, "\r\x1b[2K");
+        }
+        match message {
+            Message::Line(line) => {
+                let _ = writeln!(stderr, "{line}");
+            }
+            Message::Event(line) => {
+                let _ = writeln!(std::io::stdout().lock(), "{line}");
+            }
+            Message::Status(s) => status = s,
+            Message::Finish => break,
+        }
+        if status_area {
+            let _ = write!(stderr, "{status}");
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::{io, path::PathBuf};
+
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+use crate::{config::Config, default_path, store, store::MainMetadata};
+
+/// Actions on the skip list of a target.
+#[derive(Subcommand)]
+pub enum Action {
+    /// Lists documents on the skip list, with reasons.
+    List {
+        #[command(flatten)]
+        target: Target,
+    },
+    /// Adds a document to the skip list.
+    Add {
+        /// Id of the document.
+        id: i64,
+
+        #[command(flatten)]
+        target: Target,
+
+        /// Reason the document is skipped.
+        #[arg(long, default_value = "added manually")]
+        reason: String,
+    },
+    /// Removes a document from the skip list, so later runs back it up.
+    Remove {
+        /// Id of the document.
+        id: i64,
+
+        #[command(flatten)]
+        target: Target,
+    },
+}
+
+/// The backup directory and the target of the skip list.
+#[derive(Args)]
+pub struct Target {
+    /// Path the backup directory is.
+    path: Option<PathBuf>,
+
+    /// Configuration file of the target.
+    #[arg(short, value_name = "FILE")]
+    config: PathBuf,
+}
+
+/// Runs the action on the skip list in the metadata of the target.
+///
+/// The metadata is rewritten by backups, so it shouldn't be edited while one is running.
+pub fn run(action: Action) -> Result<()> {
+    let (Action::List { target } | Action::Add { target, .. } | Action::Remove { target, .. }) =
+        &action;
+    let config = Config::load(&target.config)?;
+    let path = target.path.clone().unwrap_or_else(default_path);
+    let meta_path = store::metadata_path(&path, &config.namespace())?;
+    let mut meta: MainMetadata = match std::fs::File::open(&meta_path) {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => MainMetadata::default(),
+        Err(err) => return Err(err.into()),
+    };
+
+    match action {
+        Action::List { .. } => {
+            let mut skip_list: Vec<_> = meta.skip_list.iter().collect();
+            skip_list.sort_by_key(|(&id, _)| id);
+            for (id, reason) in skip_list {
+                println!("document {id}: {reason}");
+            }
+            return Ok(());
+        }
+        Action::Add { id, reason, .. } => {
+            meta.skip_list.insert(id, reason);
+        }
+        Action::Remove { id, .. } => {
+            if meta.skip_list.remove(&id).is_none() {
+                eprintln!("document {id} isn't on the skip list");
+                return Ok(());
+            }
+        }
+    }
+    std::fs::create_dir_all(&path)?;
+    std::fs::write(meta_path, serde_json::to_vec_pretty(&meta)?)?;
+    Ok(())
+}
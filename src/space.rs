@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::DocMeta;
+
+/// Estimated bytes a stored document takes per word, covering all body formats.
+const BYTES_PER_WORD: u64 = 64;
+/// Estimated bytes a stored document takes besides its bodies.
+const BYTES_PER_DOC: u64 = 4 * 1024;
+
+/// Checks whether the volume of the given path can hold the documents to be backed up.
+pub fn preflight<'a, 'repo: 'a, I>(path: &Path, metas: I) -> Result<()>
+where
+    I: IntoIterator<Item = &'a DocMeta<'repo>>,
+{
+    let Some(available) = available(path)? else {
+        return Ok(());
+    };
+    let expected: u64 = metas
+        .into_iter()
+        // Word counts come from the server, and may be absurd.
+        .fold(0u64, |sum, m| {
+            sum.saturating_add(
+                m.raw
+                    .word_count
+                    .saturating_mul(BYTES_PER_WORD)
+                    .saturating_add(BYTES_PER_DOC),
+            )
+        });
+    if expected > available {
+        bail!(
+            "insufficient disk space at {}: about {} MiB expected, {} MiB available",
+            path.display(),
+            expected.div_ceil(1024 * 1024),
+            available / (1024 * 1024)
+        );
+    }
+    Ok(())
+}
+
+/// Gets the available space of the volume of the given path in bytes.
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn available(path: &Path) -> Result<Option<u64>> {
+    use std::{ffi::CString, mem::MaybeUninit, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is a valid C string, and `stat` is only read after a successful call.
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        stat.assume_init()
+    };
+    Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+/// Gets the available space of the volume of the given path in bytes.
+#[cfg(not(unix))]
+fn available(_path: &Path) -> Result<Option<u64>> {
+    Ok(None)
+}
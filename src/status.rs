@@ -0,0 +1,120 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Result};
+use time::{format_description::well_known::Iso8601, Duration, OffsetDateTime, UtcOffset};
+
+use crate::{
+    config::Config,
+    store::{self, MainMetadata},
+};
+
+/// Prints a summary of the backup directory.
+///
+/// With a configuration, only its target is summarized, and the run fails if the latest successful
+/// run is older than `max_staleness` of the configuration. With `failures`, documents failing to
+/// back up or skipped are listed.
+pub fn run(path: &Path, config: Option<&Path>, failures: bool) -> Result<()> {
+    let Some(config) = config.map(Config::load).transpose()? else {
+        for meta_path in store::metadata_paths(path)? {
+            println!("{}:", meta_path.display());
+            summarize(&meta_path, UtcOffset::UTC, failures)?;
+        }
+        return Ok(());
+    };
+
+    let age = match store::find_metadata_path(path, &config.namespace())? {
+        Some(meta_path) => summarize(&meta_path, config.timezone.offset()?, failures)?,
+        None => {
+            println!("last successful run: never");
+            None
+        }
+    };
+    if let Some(max) = config.max_staleness {
+        if age.is_none_or(|age| age > max.0) {
+            bail!("no successful run within {}", max.0);
+        }
+    }
+    Ok(())
+}
+
+/// Prints a summary of the metadata, returning the age of the latest successful run.
+fn summarize(meta_path: &Path, offset: UtcOffset, failures: bool) -> Result<Option<Duration>> {
+    let meta: MainMetadata = serde_json::from_reader(std::fs::File::open(meta_path)?)?;
+    let age = meta
+        .last_success
+        .map(|time| OffsetDateTime::now_utc() - time.0);
+    match meta.last_success {
+        Some(time) => println!(
+            "last successful run: {} ({}h ago)",
+            time.0.to_offset(offset).format(&Iso8601::DATE_TIME)?,
+            age.unwrap_or_default().whole_hours()
+        ),
+        None => println!("last successful run: never"),
+    }
+    println!(
+        "repositories: {}, {} with incomplete listings",
+        meta.books.len(),
+        meta.books.values().filter(|b| b.missing.is_some()).count()
+    );
+    println!(
+        "documents: {} tracked, {} failing, {} skipped, {} on the skip list, {} suspected truncated",
+        meta.items.len(),
+        meta.failures.len(),
+        meta.skipped.len(),
+        meta.skip_list.len(),
+        meta.items
+            .values()
+            .filter(|item| !item.suspicions.is_empty())
+            .count()
+    );
+
+    let mut hashes = HashMap::<_, usize>::new();
+    for hash in meta
+        .items
+        .values()
+        .filter_map(|item| item.body_hash.as_ref())
+    {
+        *hashes.entry(hash).or_default() += 1;
+    }
+    let duplicates: Vec<_> = hashes.into_values().filter(|&n| n > 1).collect();
+    println!(
+        "duplicates: {} documents sharing {} bodies",
+        duplicates.iter().sum::<usize>(),
+        duplicates.len()
+    );
+
+    if failures {
+        let mut failures: Vec<_> = meta.failures.iter().collect();
+        failures.sort_by_key(|(&id, _)| id);
+        for (id, failure) in failures {
+            println!(
+                "document {}: {} ({} attempts, latest {}){}",
+                id,
+                failure.error,
+                failure.attempts,
+                failure
+                    .attempted
+                    .0
+                    .to_offset(offset)
+                    .format(&Iso8601::DATE_TIME)?,
+                if meta.items.contains_key(id) {
+                    ""
+                } else {
+                    ", never backed up"
+                }
+            );
+        }
+        let mut skipped: Vec<_> = meta.skipped.iter().collect();
+        skipped.sort_by_key(|(&id, _)| id);
+        for (id, reason) in skipped {
+            println!("document {id}: skipped, {reason}");
+        }
+        let mut skip_list: Vec<_> = meta.skip_list.iter().collect();
+        skip_list.sort_by_key(|(&id, _)| id);
+        for (id, reason) in skip_list {
+            println!("document {id}: on the skip list, {reason}");
+        }
+    }
+
+    Ok(age)
+}
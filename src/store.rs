@@ -1,49 +1,483 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 
-use crate::{DocMeta, Repo};
+use crate::{quota::DailyUsage, Doc, DocMeta, Repo};
+
+/// File name of the main metadata under the backup directory, before it was namespaced by
+/// target.
+pub const METADATA_FILE: &str = "metadata.json";
+
+/// Gets the path of the main metadata of the target with the given namespace.
+///
+/// Metadata without a namespace is moved there, as directories used to hold a single target.
+pub fn metadata_path(path: &Path, namespace: &str) -> io::Result<PathBuf> {
+    let meta_path = path.join(format!("metadata-{namespace}.json"));
+    let legacy = path.join(METADATA_FILE);
+    if !meta_path.try_exists()? && legacy.try_exists()? {
+        std::fs::rename(legacy, &meta_path)?;
+    }
+    Ok(meta_path)
+}
+
+/// Finds the main metadata of the target with the given namespace, without moving metadata
+/// without a namespace.
+pub fn find_metadata_path(path: &Path, namespace: &str) -> io::Result<Option<PathBuf>> {
+    for meta_path in [
+        path.join(format!("metadata-{namespace}.json")),
+        path.join(METADATA_FILE),
+    ] {
+        if meta_path.try_exists()? {
+            return Ok(Some(meta_path));
+        }
+    }
+    Ok(None)
+}
+
+/// Lists paths of main metadata of all targets under the backup directory.
+pub fn metadata_paths(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let file = entry?.path();
+        if file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name == METADATA_FILE || name.starts_with("metadata-") && name.ends_with(".json")
+            })
+        {
+            paths.push(file);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// File marking a run directory as incomplete, removed once the run succeeds.
+///
+/// It holds the namespace of the target of the run.
+pub const INCOMPLETE_MARKER: &str = ".incomplete";
+
+/// Creates the run directory of the target, marked as incomplete until the run succeeds.
+///
+/// Fails if the directory exists, so runs never share directories.
+pub fn create_run_dir(path: &Path, namespace: &str) -> io::Result<()> {
+    std::fs::create_dir(path)?;
+    std::fs::write(path.join(INCOMPLETE_MARKER), namespace)
+}
+
+/// Creates the run directory named by the next sequence number, returning its name.
+///
+/// Numbers taken by other runs meanwhile are skipped.
+pub fn create_next_run_dir(path: &Path, quarantine: &Path, namespace: &str) -> io::Result<String> {
+    loop {
+        let name = next_run_dir(path, quarantine)?;
+        match create_run_dir(&path.join(&name), namespace) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            res => return res.map(|()| name),
+        }
+    }
+}
+
+/// Lists run directories of the target under the backup directory which are marked as incomplete.
+///
+/// Markers of earlier versions hold no namespace, and are taken as of any target.
+pub fn incomplete_runs_of(path: &Path, namespace: &str) -> io::Result<Vec<PathBuf>> {
+    let mut runs = vec![];
+    for dir in incomplete_runs(path)? {
+        let marker = std::fs::read_to_string(dir.join(INCOMPLETE_MARKER))?;
+        if marker.is_empty() || marker == namespace {
+            runs.push(dir);
+        }
+    }
+    Ok(runs)
+}
+
+/// Lists run directories under the backup directory which are marked as incomplete, of any target.
+pub fn incomplete_runs(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut runs = vec![];
+    for entry in std::fs::read_dir(path)? {
+        let dir = entry?.path();
+        if dir.is_dir() && dir.join(INCOMPLETE_MARKER).try_exists()? {
+            runs.push(dir);
+        }
+    }
+    runs.sort();
+    Ok(runs)
+}
+
+/// File name of the index of documents saved in a run, under the run directory.
+pub const INDEX_FILE: &str = "index.json";
+
+/// An entry of the index of a run, for locating a document without opening every file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Slug of the repository.
+    pub repo: String,
+    pub slug: String,
+    pub title: String,
+    /// Path of the document file, relative to the run directory.
+    pub path: String,
+    pub updated_at: BackupTime,
+    /// Whether the document was backed up for the first time.
+    pub new: bool,
+}
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct MainMetadata {
     pub items: HashMap<i64, MetaItem>,
-    pub books: HashMap<i64, Repo>,
+    pub books: HashMap<i64, BookItem>,
+    /// Reasons documents were skipped, removed once backed up.
+    #[serde(default)]
+    pub skipped: HashMap<i64, String>,
+    /// Documents never backed up, with reasons, edited with the `skip` command.
+    #[serde(default)]
+    pub skip_list: HashMap<i64, String>,
+    /// Failures of documents to back up, removed once backed up.
+    #[serde(default)]
+    pub failures: HashMap<i64, FailureItem>,
+    /// Start time of the latest run which completed without errors aborting it.
+    #[serde(default)]
+    pub last_success: Option<BackupTime>,
+    /// Runs of the target, oldest first.
+    #[serde(default)]
+    pub runs: Vec<RunItem>,
+    /// Usage counted against daily quotas on the day of the latest run.
+    #[serde(default)]
+    pub daily: Option<DailyUsage>,
+}
+
+/// A failure of a document to back up.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FailureItem {
+    pub error: String,
+    /// Time of the latest attempt.
+    pub attempted: BackupTime,
+    /// Consecutive failed attempts.
+    pub attempts: u32,
+}
+
+/// A run of the target.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunItem {
+    /// Name of the run directory, unless it wasn't created.
+    ///
+    /// Names of runs without directories may be taken by later runs.
+    #[serde(default)]
+    pub dir: Option<String>,
+    pub started: BackupTime,
+    /// Whether nothing changed since the previous run, so the run directory wasn't created.
+    #[serde(default)]
+    pub noop: bool,
+    /// Error the run aborted with, if any.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Prefix of run directories named by sequence numbers.
+const RUN_PREFIX: &str = "run-";
+
+/// Gets the name of the next run directory named by sequence numbers.
+///
+/// Numbers of runs moved into `quarantine` aren't reused either.
+pub fn next_run_dir(path: &Path, quarantine: &Path) -> io::Result<String> {
+    let mut last = 0u64;
+    for dir in [path, quarantine] {
+        if !dir.try_exists()? {
+            continue;
+        }
+        for entry in std::fs::read_dir(dir)? {
+            if let Some(n) = entry?
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix(RUN_PREFIX))
+                .and_then(|n| n.parse::<u64>().ok())
+            {
+                last = last.max(n);
+            }
+        }
+    }
+    Ok(format!("{RUN_PREFIX}{:06}", last + 1))
+}
+
+/// A tracked repository.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BookItem {
+    #[serde(flatten)]
+    pub repo: Repo,
+    #[serde(default)]
+    pub first_seen: Option<BackupTime>,
+    #[serde(default)]
+    pub last_seen: Option<BackupTime>,
+    /// Number of documents in the repository missing from its latest listing, if any.
+    #[serde(default)]
+    pub missing: Option<u64>,
+    /// Update time of the repository when all its listed documents were last backed up.
+    #[serde(default)]
+    pub synced: Option<BackupTime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[serde(transparent)]
-pub struct BackupTime(#[serde(with = "time::serde::iso8601")] OffsetDateTime);
+pub struct BackupTime(#[serde(with = "time::serde::iso8601")] pub OffsetDateTime);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MetaItem {
     pub last_updated: BackupTime,
     pub backups: Vec<BackupTime>,
+    /// Slugs the document has had, the latest one last.
+    #[serde(default)]
+    pub slugs: Vec<String>,
+    /// Publish status of the latest backup.
+    #[serde(default)]
+    pub status: Option<i32>,
+    /// Visibility of the latest backup.
+    #[serde(default)]
+    pub public: Option<i32>,
+    /// Reasons the latest backup is suspected to be truncated.
+    #[serde(default)]
+    pub suspicions: Vec<Suspicion>,
+    /// Hash of the bodies of the latest backup, for finding duplicates.
+    #[serde(default)]
+    pub body_hash: Option<String>,
+}
+
+/// Hashes the bodies of the document with 64-bit FNV-1a, if it has any.
+///
+/// The hash is stable across builds, unlike those of [`std::hash`].
+pub fn body_hash(doc: &Doc) -> Option<String> {
+    let bodies = [&doc.body, &doc.body_sheet, &doc.body_html, &doc.body_lake];
+    if bodies
+        .iter()
+        .all(|body| body.as_deref().is_none_or(str::is_empty))
+    {
+        return None;
+    }
+    let mut hash = 0xcbf29ce484222325u64;
+    for body in bodies {
+        // Separated by a byte never in UTF-8, so moving text between bodies changes the hash.
+        for &byte in body
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes()
+            .iter()
+            .chain(&[0xff])
+        {
+            hash = (hash ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+    Some(format!("{hash:016x}"))
+}
+
+/// A reason a stored document is suspected to be truncated.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Suspicion {
+    /// The HTML body doesn't end with a tag.
+    #[serde(rename = "malformed_html")]
+    MalformedHtml,
+    /// The body is much shorter than the word count of the document.
+    #[serde(rename = "short_body")]
+    ShortBody,
+}
+
+impl Suspicion {
+    /// Checks the given document with its word count for signs of truncation.
+    pub fn check(doc: &Doc, word_count: u64) -> Vec<Self> {
+        let mut suspicions = vec![];
+        if doc
+            .body_html
+            .as_deref()
+            .map(str::trim_end)
+            .is_some_and(|html| !html.is_empty() && !html.ends_with('>'))
+        {
+            suspicions.push(Self::MalformedHtml);
+        }
+        // Each word, or CJK character, takes at least one character of the body.
+        if doc.body.as_deref().is_some_and(|body| {
+            (body.chars().filter(|c| !c.is_whitespace()).count() as u64) < word_count / 2
+        }) {
+            suspicions.push(Self::ShortBody);
+        }
+        suspicions
+    }
 }
 
 impl MainMetadata {
+    /// Tracks the listed repository at the given time.
+    ///
+    /// Returns the previous repository if its name or slug has changed.
+    pub fn track_repo(&mut self, repo: &Repo, now: OffsetDateTime) -> Option<Repo> {
+        let time = Some(BackupTime(now));
+        if let Some(b) = self.books.get_mut(&repo.id) {
+            let old = std::mem::replace(&mut b.repo, repo.clone());
+            b.first_seen = b.first_seen.or(time);
+            b.last_seen = time;
+            (old.name != repo.name || old.slug != repo.slug).then_some(old)
+        } else {
+            self.books.insert(
+                repo.id,
+                BookItem {
+                    repo: repo.clone(),
+                    first_seen: time,
+                    last_seen: time,
+                    missing: None,
+                    synced: None,
+                },
+            );
+            None
+        }
+    }
+
+    /// Tracks the number of documents listed for the tracked repository.
+    ///
+    /// Returns the number of documents the repository has but weren't listed, if any.
+    pub fn track_listing(&mut self, repo: &Repo, listed: usize) -> Option<u64> {
+        let missing = repo
+            .items_count
+            .map(|count| count.saturating_sub(listed as u64))
+            .filter(|&missing| missing > 0);
+        if let Some(b) = self.books.get_mut(&repo.id) {
+            b.missing = missing;
+        }
+        missing
+    }
+
+    /// Whether all documents of the repository were backed up since it was last updated.
+    pub fn synced(&self, repo: &Repo) -> bool {
+        self.books
+            .get(&repo.id)
+            .and_then(|b| b.synced)
+            .is_some_and(|time| time.0 == repo.updated_at)
+    }
+
+    /// Tracks that all listed documents of the repository are backed up.
+    pub fn track_synced(&mut self, repo: &Repo) {
+        if let Some(b) = self.books.get_mut(&repo.id) {
+            b.synced = Some(BackupTime(repo.updated_at));
+        }
+    }
+
     /// Whether document with the given metadata needs a new backup.
     pub fn needs_backup(&self, meta: &DocMeta<'_>) -> bool {
-        !self
-            .items
+        self.items
             .get(&meta.raw.id)
-            .is_some_and(|m| m.last_updated.0 >= meta.raw.updated_at)
+            .is_none_or(|m| m.last_updated.0 < meta.raw.updated_at)
     }
 
-    /// Tracks the backed-up metadata.
-    pub fn track_backup(&mut self, meta: &DocMeta<'_>) {
+    /// Tracks a failed attempt to back up the document at the given time.
+    pub fn track_failure(&mut self, id: i64, error: String, now: OffsetDateTime) {
+        let attempts = self.failures.get(&id).map_or(0, |f| f.attempts);
+        self.failures.insert(
+            id,
+            FailureItem {
+                error,
+                attempted: BackupTime(now),
+                attempts: attempts + 1,
+            },
+        );
+    }
+
+    /// Whether the document is identical to its latest backup, with the same update time and bodies.
+    pub fn unchanged(&self, meta: &DocMeta<'_>, doc: &Doc) -> bool {
+        self.items.get(&meta.raw.id).is_some_and(|m| {
+            m.last_updated.0 == meta.raw.updated_at
+                && m.body_hash.is_some()
+                && m.body_hash == body_hash(doc)
+        })
+    }
+
+    /// Tracks the backed-up document with its metadata, and suspicions of truncation of the backup.
+    ///
+    /// Returns the previous slug of the document if it has changed.
+    pub fn track_backup(
+        &mut self,
+        meta: &DocMeta<'_>,
+        doc: &Doc,
+        suspicions: Vec<Suspicion>,
+    ) -> Option<String> {
         let time = BackupTime(meta.raw.updated_at);
+        let hash = body_hash(doc);
+        self.skipped.remove(&meta.raw.id);
+        self.failures.remove(&meta.raw.id);
         if let Some(m) = self.items.get_mut(&meta.raw.id) {
             m.last_updated = time;
             m.backups.push(time);
+            m.status = meta.raw.status;
+            m.public = meta.raw.public;
+            m.suspicions = suspicions;
+            m.body_hash = hash;
+            if m.slugs.last() != Some(&meta.raw.slug) {
+                let old = m.slugs.last().cloned();
+                m.slugs.push(meta.raw.slug.clone());
+                return old;
+            }
         } else {
             self.items.insert(
                 meta.raw.id,
                 MetaItem {
                     last_updated: time,
                     backups: vec![time],
+                    slugs: vec![meta.raw.slug.clone()],
+                    status: meta.raw.status,
+                    public: meta.raw.public,
+                    suspicions,
+                    body_hash: hash,
                 },
             );
         }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_dirs_continue_the_sequence() {
+        let dir = std::env::temp_dir().join(format!("yuque-squirrel-{}-runs", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let quarantine = dir.join("quarantine");
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(next_run_dir(&dir, &quarantine).unwrap(), "run-000001");
+
+        for name in ["run-000002", "run-x", "2024-06-01T00:00:00.000000000"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        assert_eq!(next_run_dir(&dir, &quarantine).unwrap(), "run-000003");
+
+        std::fs::create_dir_all(quarantine.join("run-000005")).unwrap();
+        assert_eq!(next_run_dir(&dir, &quarantine).unwrap(), "run-000006");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn run_dirs_are_never_shared() {
+        let dir =
+            std::env::temp_dir().join(format!("yuque-squirrel-{}-shared-runs", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let quarantine = dir.join("quarantine");
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(
+            create_next_run_dir(&dir, &quarantine, "a").unwrap(),
+            "run-000001"
+        );
+        assert_eq!(
+            create_next_run_dir(&dir, &quarantine, "a").unwrap(),
+            "run-000002"
+        );
+        assert!(dir.join("run-000002").join(INCOMPLETE_MARKER).is_file());
+        assert_eq!(
+            create_run_dir(&dir.join("run-000001"), "b")
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
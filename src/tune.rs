@@ -0,0 +1,184 @@
+use std::{
+    cell::Cell,
+    time::{Duration, Instant},
+};
+
+use reqwest::StatusCode;
+
+/// Request limitation per second the adaptive mode starts with.
+const INITIAL_LIMIT: usize = 4;
+/// Chunk size used when the adaptive mode is disabled.
+const FIXED_CHUNK_SIZE: usize = 16;
+/// Responses slower than this factor of the baseline latency are considered slow.
+const SLOW_FACTOR: u32 = 3;
+/// Weight of the baseline latency against each new latency, so it follows lasting changes.
+const BASELINE_WEIGHT: u32 = 8;
+/// Window the limitation is adjusted at most once in, that of the request limitation.
+const WINDOW: Duration = Duration::from_secs(1);
+
+/// Controller of request rate and concurrency.
+///
+/// In adaptive mode, the limitation increases additively while responses stay fast, and decreases
+/// when responses slow down, by one per window, or is halved when the server answers with `429`.
+#[derive(Debug)]
+pub struct Tuner {
+    adaptive: bool,
+    max: usize,
+    limit: Cell<usize>,
+    /// Moving average of latencies, as the reference of a fast response.
+    baseline: Cell<Option<Duration>>,
+    /// When the limitation was last adjusted by latency.
+    adjusted: Cell<Option<Instant>>,
+}
+
+impl Tuner {
+    /// Creates a new tuner with the given upper bound of request limitation.
+    pub fn new(max: usize, adaptive: bool) -> Self {
+        let max = max.max(1);
+        Self {
+            adaptive,
+            max,
+            limit: Cell::new(if adaptive {
+                INITIAL_LIMIT.min(max)
+            } else {
+                max
+            }),
+            baseline: Cell::new(None),
+            adjusted: Cell::new(None),
+        }
+    }
+
+    /// Current request limitation per second.
+    #[inline]
+    pub fn limit(&self) -> usize {
+        self.limit.get()
+    }
+
+    /// Current number of documents to be fetched concurrently.
+    #[inline]
+    pub fn chunk_size(&self) -> usize {
+        if self.adaptive {
+            self.limit.get().div_ceil(2)
+        } else {
+            FIXED_CHUNK_SIZE
+        }
+    }
+
+    /// Observes a response with its latency and status.
+    #[inline]
+    pub fn observe(&self, latency: Duration, status: StatusCode) {
+        self.observe_at(latency, status, Instant::now());
+    }
+
+    fn observe_at(&self, latency: Duration, status: StatusCode, now: Instant) {
+        if !self.adaptive {
+            return;
+        }
+        let limit = self.limit.get();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            self.limit.set((limit / 2).max(1));
+            return;
+        }
+        let baseline = self.baseline.get().unwrap_or(latency);
+        self.baseline.set(Some(
+            (baseline * (BASELINE_WEIGHT - 1) + latency) / BASELINE_WEIGHT,
+        ));
+        if self
+            .adjusted
+            .get()
+            .is_some_and(|adjusted| now < adjusted + WINDOW)
+        {
+            return;
+        }
+        self.adjusted.set(Some(now));
+        if latency > baseline * SLOW_FACTOR {
+            self.limit.set(limit.saturating_sub(1).max(1));
+        } else {
+            self.limit.set((limit + 1).min(self.max));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn limit_increases_once_per_window() {
+        let tuner = Tuner::new(10, true);
+        let start = Instant::now();
+        for _ in 0..5 {
+            tuner.observe_at(FAST, StatusCode::OK, start);
+        }
+        assert_eq!(tuner.limit(), INITIAL_LIMIT + 1);
+        tuner.observe_at(FAST, StatusCode::OK, start + WINDOW);
+        assert_eq!(tuner.limit(), INITIAL_LIMIT + 2);
+    }
+
+    #[test]
+    fn limit_is_bounded() {
+        let tuner = Tuner::new(5, true);
+        let start = Instant::now();
+        for i in 0..10 {
+            tuner.observe_at(FAST, StatusCode::OK, start + WINDOW * i);
+        }
+        assert_eq!(tuner.limit(), 5);
+    }
+
+    #[test]
+    fn slow_responses_decrease_the_limit() {
+        let tuner = Tuner::new(10, true);
+        let start = Instant::now();
+        tuner.observe_at(FAST, StatusCode::OK, start);
+        tuner.observe_at(FAST * 10, StatusCode::OK, start + WINDOW);
+        assert_eq!(tuner.limit(), INITIAL_LIMIT);
+    }
+
+    #[test]
+    fn baseline_follows_lasting_slowdowns() {
+        let tuner = Tuner::new(10, true);
+        let start = Instant::now();
+        // A single fast response doesn't make all later ones slow.
+        tuner.observe_at(FAST / 10, StatusCode::OK, start);
+        for i in 1..=30 {
+            tuner.observe_at(FAST, StatusCode::OK, start + WINDOW * i);
+        }
+        assert_eq!(tuner.limit(), 10);
+    }
+
+    #[test]
+    fn too_many_requests_halve_the_limit() {
+        let tuner = Tuner::new(10, true);
+        tuner.observe_at(
+            Duration::ZERO,
+            StatusCode::TOO_MANY_REQUESTS,
+            Instant::now(),
+        );
+        assert_eq!(tuner.limit(), INITIAL_LIMIT / 2);
+        tuner.observe_at(
+            Duration::ZERO,
+            StatusCode::TOO_MANY_REQUESTS,
+            Instant::now(),
+        );
+        tuner.observe_at(
+            Duration::ZERO,
+            StatusCode::TOO_MANY_REQUESTS,
+            Instant::now(),
+        );
+        assert_eq!(tuner.limit(), 1);
+    }
+
+    #[test]
+    fn fixed_mode_ignores_responses() {
+        let tuner = Tuner::new(10, false);
+        tuner.observe_at(
+            Duration::ZERO,
+            StatusCode::TOO_MANY_REQUESTS,
+            Instant::now(),
+        );
+        assert_eq!(tuner.limit(), 10);
+        assert_eq!(tuner.chunk_size(), FIXED_CHUNK_SIZE);
+    }
+}
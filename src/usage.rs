@@ -0,0 +1,79 @@
+use std::{
+    cell::Cell,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+/// Usage of the API during a run.
+#[derive(Debug)]
+pub struct Usage {
+    start: Instant,
+    requests: Cell<usize>,
+    /// Wall-clock time requests were held back by the rate limiter.
+    stalled: Cell<Duration>,
+    /// The limiter window of the last stall, counted only once for concurrent waiters.
+    window: Cell<Option<Instant>>,
+}
+
+impl Usage {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            requests: Cell::new(0),
+            stalled: Cell::new(Duration::ZERO),
+            window: Cell::new(None),
+        }
+    }
+
+    /// Records a request sent.
+    #[inline]
+    pub fn request(&self) {
+        self.requests.set(self.requests.get() + 1);
+    }
+
+    /// Records a stall of the limiter window started at the given instant.
+    pub fn stall(&self, window: Instant, duration: Duration) {
+        if self.window.get() != Some(window) {
+            self.window.set(Some(window));
+            self.stalled.set(self.stalled.get() + duration);
+        }
+    }
+
+    /// Summary of the usage against the given request limitation per second.
+    #[inline]
+    pub fn summary(&self, limit: usize) -> Summary<'_> {
+        Summary { usage: self, limit }
+    }
+}
+
+/// Human-readable summary of [`Usage`].
+#[derive(Debug)]
+pub struct Summary<'a> {
+    usage: &'a Usage,
+    limit: usize,
+}
+
+impl Display for Summary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let elapsed = self.usage.start.elapsed();
+        let requests = self.usage.requests.get();
+        let stalled = self.usage.stalled.get();
+        write!(
+            f,
+            "{} API requests in {:.1}s, averaging {:.1} of {} requests per second",
+            requests,
+            elapsed.as_secs_f64(),
+            requests as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            self.limit
+        )?;
+        if !stalled.is_zero() {
+            write!(
+                f,
+                "\n{:.1}s spent waiting for the rate limit, the run could take about {:.1}s with a higher limit",
+                stalled.as_secs_f64(),
+                elapsed.saturating_sub(stalled).as_secs_f64()
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::{
+    store::{self, MainMetadata},
+    Doc,
+};
+
+/// Verifies the metadata and stored documents of the backup directory.
+///
+/// Documents are checked to be well-formed JSON, and not suspected to be truncated when backed up.
+/// With `deep`, they are further validated against [`Doc`], and checked for a body and an id
+/// matching the file name.
+pub fn run(path: &Path, deep: bool) -> Result<()> {
+    let mut problems = 0usize;
+    for meta_path in store::metadata_paths(path)? {
+        match serde_json::from_reader::<_, MainMetadata>(std::fs::File::open(&meta_path)?) {
+            Ok(meta) => {
+                for (id, item) in &meta.items {
+                    if !item.suspicions.is_empty() {
+                        eprintln!(
+                            "document {} is suspected to be truncated: {:?}",
+                            id, item.suspicions
+                        );
+                        problems += 1;
+                    }
+                }
+            }
+            Err(err) => {
+                eprintln!("{}: {}", meta_path.display(), err);
+                problems += 1;
+            }
+        }
+    }
+
+    for dir in store::incomplete_runs(path)? {
+        eprintln!("run {} is incomplete", dir.display());
+        problems += 1;
+    }
+
+    let mut checked = 0usize;
+    for entry in std::fs::read_dir(path)? {
+        let dir = entry?.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in std::fs::read_dir(&dir)? {
+            let file = entry?.path();
+            let Some(id) = doc_id(&file) else {
+                continue;
+            };
+            checked += 1;
+            if let Err(err) = check(&file, id, deep) {
+                eprintln!("{}: {}", file.display(), err);
+                problems += 1;
+            }
+        }
+    }
+
+    println!("verified {checked} documents, {problems} problems found");
+    if problems > 0 {
+        bail!("backup verification failed");
+    }
+    Ok(())
+}
+
+/// Parses the document id from a stored document file name.
+pub fn doc_id(file: &Path) -> Option<i64> {
+    file.file_name()?
+        .to_str()?
+        .strip_prefix("doc")?
+        .strip_suffix(".json")?
+        .parse()
+        .ok()
+}
+
+fn check(file: &Path, id: i64, deep: bool) -> Result<()> {
+    let bytes = std::fs::read(file)?;
+    if !deep {
+        serde_json::from_slice::<serde_json::Value>(&bytes)?;
+        return Ok(());
+    }
+
+    let doc: Doc = serde_json::from_slice(&bytes)?;
+    if doc.id != id {
+        bail!("document id {} mismatches the file name", doc.id);
+    }
+    if doc.body.is_none()
+        && doc.body_sheet.is_none()
+        && doc.body_html.is_none()
+        && doc.body_lake.is_none()
+    {
+        bail!("document has no body");
+    }
+    Ok(())
+}